@@ -0,0 +1,34 @@
+//! Times sandmark.umz on each engine: `cargo bench [-- ENGINE..]`, where
+//! ENGINE is `reference`, `fast` or, with `--features jit`, `jit`, and all
+//! of them run by default. Engines other than the reference one are
+//! compared with it, when it runs too.
+
+use um::{selftest, Engine};
+
+fn main() {
+    let names: Vec<String> = std::env::args().skip(1).filter(|a| !a.starts_with("--")).collect();
+    let engines = [
+        ("reference", Engine::Reference),
+        ("fast", Engine::Fast),
+        #[cfg(feature = "jit")]
+        ("jit", Engine::Jit),
+    ];
+    let mut reference = None;
+    for (name, engine) in engines {
+        if !names.is_empty() && !names.iter().any(|n| n == name) {
+            continue;
+        }
+        let seconds = match selftest::sandmark(engine) {
+            Ok(elapsed) => elapsed.as_secs_f64(),
+            Err(failure) => panic!("{failure}"),
+        };
+        match reference {
+            None if engine == Engine::Reference => {
+                reference = Some(seconds);
+                println!("sandmark/{name:<10} {seconds:>8.2}s");
+            }
+            Some(base) => println!("sandmark/{name:<10} {seconds:>8.2}s  {:.2}x reference", base / seconds),
+            None => println!("sandmark/{name:<10} {seconds:>8.2}s"),
+        }
+    }
+}
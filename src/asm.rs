@@ -0,0 +1,365 @@
+//! An assembler for the text form printed by the disassembler.
+//!
+//! ```text
+//! ; Prints "Hi" and halts.
+//!         orth r1, message
+//!         orth r2, 1
+//! loop:   index r3, r0, r1    ; r0 is 0, so this reads array 0
+//!         ...
+//! message:
+//!         .data "Hi", 0
+//! ```
+//!
+//! Each line holds any number of `label:` definitions followed by at most
+//! one instruction or `.data` directive. Registers are `r0` to `r7`,
+//! numbers are decimal, `0x` hexadecimal or `'c'` characters, and labels
+//! stand for the offset of what follows them. A `.data` directive emits
+//! one platter per number or label and one per byte of a string literal.
+//! Comments run from `;` to the end of the line.
+
+use std::{collections::HashMap, fmt};
+use crate::{
+    instruction::{Instruction, RawInstruction},
+    memory::{ArrayOfPlatters, Platter},
+    op::{Op, Operand},
+    program::Program,
+    register,
+};
+
+/// The largest value `Op::Orth` can load.
+const MAX_VALUE: u64 = 0x1ff_ffff;
+
+/// Why a program could not be assembled, and where.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Error {
+    /// The line of the error, counting from 1.
+    pub line: usize,
+    /// The column of the error, counting from 1.
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    Name(String),
+    Number(u64),
+    Str(Vec<u8>),
+    Comma,
+    Colon,
+}
+
+/// A token and the column it starts at.
+type Spanned = (usize, Token);
+
+/// A platter whose value may depend on a label.
+enum Word {
+    Platter(Platter),
+    /// An instruction or data word to which the offset of a label is added.
+    Label { base: Platter, label: String, column: usize, max: u64 },
+}
+
+struct Assembler {
+    line: usize,
+    words: Vec<(usize, Word)>,
+    labels: HashMap<String, usize>,
+}
+
+/// Assembles `source` into a program.
+pub fn assemble(source: &str) -> Result<Program, Error> {
+    let mut asm = Assembler {
+        line: 0,
+        words: Vec::new(),
+        labels: HashMap::new(),
+    };
+    for (i, text) in source.lines().enumerate() {
+        asm.line = i + 1;
+        let tokens = asm.tokenize(text)?;
+        asm.statement(&tokens, text.chars().count() + 1)?;
+    }
+    let mut platters = Vec::with_capacity(asm.words.len());
+    for (line, word) in &asm.words {
+        platters.push(match word {
+            Word::Platter(p) => *p,
+            Word::Label { base, label, column, max } => {
+                let error = |message: String| Error { line: *line, column: *column, message };
+                let &offset = asm.labels.get(label).ok_or_else(|| error(format!("undefined label `{label}`")))?;
+                if offset as u64 > *max {
+                    return Err(error(format!("label `{label}` at {offset} does not fit in {max:#x}")));
+                }
+                base | offset as Platter
+            }
+        });
+    }
+    let array: ArrayOfPlatters = platters.into();
+    Ok(array.into())
+}
+
+impl Assembler {
+    fn error(&self, column: usize, message: impl Into<String>) -> Error {
+        Error {
+            line: self.line,
+            column,
+            message: message.into(),
+        }
+    }
+    fn tokenize(&self, text: &str) -> Result<Vec<Spanned>, Error> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let column = i + 1;
+            let c = chars[i];
+            i += 1;
+            let token = match c {
+                ';' => break,
+                c if c.is_whitespace() => continue,
+                ',' => Token::Comma,
+                ':' => Token::Colon,
+                '"' => {
+                    let mut bytes = Vec::new();
+                    loop {
+                        match chars.get(i) {
+                            None => return Err(self.error(column, "unterminated string")),
+                            Some('"') => break,
+                            Some('\\') => {
+                                let (byte, len) = self.escape(&chars[i + 1..], i + 1)?;
+                                bytes.push(byte);
+                                i += len;
+                            }
+                            Some(&c) if c.is_ascii() => bytes.push(c as u8),
+                            Some(&c) => bytes.extend(c.to_string().bytes()),
+                        }
+                        i += 1;
+                    }
+                    i += 1;
+                    Token::Str(bytes)
+                }
+                '\'' => {
+                    let (byte, len) = match chars.get(i) {
+                        Some('\\') => {
+                            let (byte, len) = self.escape(&chars[i + 1..], i + 1)?;
+                            (byte, len + 1)
+                        }
+                        Some(&c) if c.is_ascii() && c != '\'' => (c as u8, 1),
+                        _ => return Err(self.error(column, "bad character literal")),
+                    };
+                    i += len;
+                    if chars.get(i) != Some(&'\'') {
+                        return Err(self.error(column, "unterminated character literal"));
+                    }
+                    i += 1;
+                    Token::Number(byte as u64)
+                }
+                c if c.is_ascii_digit() => {
+                    let start = i - 1;
+                    while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                        i += 1;
+                    }
+                    let text: String = chars[start..i].iter().collect();
+                    let parsed = match text.strip_prefix("0x") {
+                        Some(hex) => u64::from_str_radix(hex, 16),
+                        None => text.parse(),
+                    };
+                    Token::Number(parsed.map_err(|_| self.error(column, format!("bad number `{text}`")))?)
+                }
+                c if c.is_alphabetic() || c == '_' || c == '.' => {
+                    let start = i - 1;
+                    while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') {
+                        i += 1;
+                    }
+                    Token::Name(chars[start..i].iter().collect())
+                }
+                c => return Err(self.error(column, format!("unexpected character `{c}`"))),
+            };
+            tokens.push((column, token));
+        }
+        Ok(tokens)
+    }
+    /// Decodes the escape sequence following a backslash at `column`,
+    /// returning the byte and the number of characters it took.
+    fn escape(&self, chars: &[char], column: usize) -> Result<(u8, usize), Error> {
+        let byte = match chars.first() {
+            Some('n') => b'\n',
+            Some('t') => b'\t',
+            Some('r') => b'\r',
+            Some('0') => 0,
+            Some('\\') => b'\\',
+            Some('"') => b'"',
+            Some('\'') => b'\'',
+            Some('x') => {
+                let hex: String = chars.iter().skip(1).take(2).collect();
+                return match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) if hex.len() == 2 => Ok((byte, 3)),
+                    _ => Err(self.error(column, "bad escape sequence")),
+                };
+            }
+            _ => return Err(self.error(column, "bad escape sequence")),
+        };
+        Ok((byte, 1))
+    }
+    fn statement(&mut self, mut tokens: &[Spanned], end: usize) -> Result<(), Error> {
+        while let [(column, Token::Name(label)), (_, Token::Colon), rest @ ..] = tokens {
+            if self.labels.insert(label.clone(), self.words.len()).is_some() {
+                return Err(self.error(*column, format!("label `{label}` is already defined")));
+            }
+            tokens = rest;
+        }
+        match tokens {
+            [] => Ok(()),
+            [(_, Token::Name(name)), rest @ ..] if name == ".data" => self.data(rest, end),
+            [(column, Token::Name(name)), rest @ ..] => {
+                let op: Op = name.parse().map_err(|e: String| self.error(*column, e))?;
+                self.instruction(op, rest, end)
+            }
+            [(column, _), ..] => Err(self.error(*column, "expected a label, an instruction or .data")),
+        }
+    }
+    fn data(&mut self, tokens: &[Spanned], end: usize) -> Result<(), Error> {
+        if tokens.is_empty() {
+            return Err(self.error(end, ".data needs at least one value"));
+        }
+        for item in tokens.split(|(_, t)| *t == Token::Comma) {
+            match item {
+                [(_, Token::Str(bytes))] => {
+                    let words = bytes.iter().map(|&b| (self.line, Word::Platter(b as Platter)));
+                    self.words.extend(words);
+                }
+                [(column, Token::Number(n))] => {
+                    let p = Platter::try_from(*n)
+                        .map_err(|_| self.error(*column, format!("{n:#x} does not fit in a platter")))?;
+                    self.words.push((self.line, Word::Platter(p)));
+                }
+                [(column, Token::Name(label))] => self.words.push((self.line, Word::Label {
+                    base: 0,
+                    label: label.clone(),
+                    column: *column,
+                    max: Platter::MAX as u64,
+                })),
+                [] => return Err(self.error(end, "expected a value")),
+                [(column, _), ..] => return Err(self.error(*column, "expected a number, a string or a label")),
+            }
+        }
+        Ok(())
+    }
+    fn instruction(&mut self, op: Op, tokens: &[Spanned], end: usize) -> Result<(), Error> {
+        let operands = op.operands();
+        let mut i = Instruction {
+            op,
+            a: 0.into(),
+            b: 0.into(),
+            c: 0.into(),
+            sa: 0.into(),
+            value: 0.into(),
+        };
+        let mut label = None;
+        let mut tokens = tokens.iter();
+        for (n, operand) in operands.iter().enumerate() {
+            if n > 0 {
+                match tokens.next() {
+                    Some((_, Token::Comma)) => {}
+                    Some((column, _)) => return Err(self.error(*column, "expected `,`")),
+                    None => return Err(self.error(end, format!("{} takes {} operands", op.mnemonic(), operands.len()))),
+                }
+            }
+            let (column, token) = tokens
+                .next()
+                .ok_or_else(|| self.error(end, format!("{} takes {} operands", op.mnemonic(), operands.len())))?;
+            match (operand, token) {
+                (Operand::Value, Token::Number(n)) if *n <= MAX_VALUE => i.value = (*n as Platter).into(),
+                (Operand::Value, Token::Number(n)) => {
+                    return Err(self.error(*column, format!("{n:#x} does not fit in {MAX_VALUE:#x}")));
+                }
+                (Operand::Value, Token::Name(name)) => label = Some((name.clone(), *column)),
+                (Operand::Value, _) => return Err(self.error(*column, "expected a number or a label")),
+                (register, Token::Name(name)) => {
+                    let r = self.register(name, *column)?;
+                    match register {
+                        Operand::A if op == Op::Orth => i.sa = r,
+                        Operand::A => i.a = r,
+                        Operand::B => i.b = r,
+                        _ => i.c = r,
+                    }
+                }
+                (_, _) => return Err(self.error(*column, "expected a register")),
+            }
+        }
+        if let Some((column, _)) = tokens.next() {
+            return Err(self.error(*column, format!("{} takes {} operands", op.mnemonic(), operands.len())));
+        }
+        let raw: RawInstruction = i.into();
+        let base: Platter = raw.into();
+        self.words.push((self.line, match label {
+            None => Word::Platter(base),
+            Some((label, column)) => Word::Label { base, label, column, max: MAX_VALUE },
+        }));
+        Ok(())
+    }
+    fn register(&self, name: &str, column: usize) -> Result<register::Index, Error> {
+        match name.strip_prefix('r').and_then(|n| n.parse::<u32>().ok()) {
+            Some(n) if n < 8 => Ok(n.into()),
+            _ => Err(self.error(column, format!("`{name}` is not a register"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn platters(source: &str) -> Vec<Platter> {
+        assemble(source).unwrap().as_slice().to_vec()
+    }
+
+    fn error(source: &str) -> (usize, usize) {
+        let e = assemble(source).unwrap_err();
+        (e.line, e.column)
+    }
+
+    #[test]
+    fn instructions() {
+        let source = "
+            cmov r1, r2, r3
+            add r1,r2,r3 ; comment
+            halt
+            alloc r2, r1
+            out r6
+            orth r3, 0x1becafe
+        ";
+        assert_eq!(platters(source), [0x0000_0053, 0x3000_0053, 0x7000_0000, 0x8000_0011, 0xa000_0006, 0xd7be_cafe]);
+    }
+
+    #[test]
+    fn labels_and_data() {
+        let source = "
+            start: orth r1, end
+            loop:
+            load r0, r1
+            .data 'A', \"b\\n\", loop, 0xdeadbeef
+            end: end2: .data start
+        ";
+        assert_eq!(platters(source), [0xd200_0007, 0xc000_0001, 0x41, 0x62, 0x0a, 0x01, 0xdead_beef, 0x00]);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("halt\n  bogus r1"), (2, 3));
+        assert_eq!(error("add r1, r2"), (1, 11));
+        assert_eq!(error("add r1, r8, r2"), (1, 9));
+        assert_eq!(error("orth r1, 0x2000000"), (1, 10));
+        assert_eq!(error("orth r1, nowhere"), (1, 10));
+        assert_eq!(error("a: halt\na: halt"), (2, 1));
+        assert_eq!(error(".data \"abc"), (1, 7));
+        assert_eq!(error("halt r1"), (1, 6));
+        assert_eq!(error("out r1 r2"), (1, 8));
+        assert_eq!(error(".data 1,"), (1, 9));
+        assert_eq!(assemble("cmov r1 r2").unwrap_err().to_string(), "1:9: expected `,`");
+    }
+}
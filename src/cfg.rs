@@ -0,0 +1,346 @@
+//! Recovers the control-flow graph of a program, and exports it as
+//! Graphviz DOT or JSON.
+//!
+//! A Load of the array in a register known to hold 0 is a jump to the
+//! offsets its C register may hold. What registers may hold is tracked from
+//! the entry, where every register is 0, through Orth, Move and arithmetic,
+//! as sets of up to `MAX_VALUES` constants. A Move of one constant or
+//! another, depending on a register that is not known, thus becomes a
+//! conditional jump. Code that only runs after array 0 is amended or
+//! replaced is not found.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    io::{self, Write},
+};
+use crate::{
+    decode::Decoded,
+    disasm::{self, Line},
+    json::Json,
+    memory::Platter,
+    op::Op,
+    program::Program,
+    register::{Index, NUMBER_OF_REGISTERS},
+};
+
+/// The most constants a register is tracked as possibly holding.
+const MAX_VALUES: usize = 8;
+
+/// What a register may hold.
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Value {
+    /// One of these constants.
+    Known(BTreeSet<Platter>),
+    Unknown,
+}
+
+impl Value {
+    fn constant(p: Platter) -> Self {
+        Self::Known(BTreeSet::from([p]))
+    }
+    fn capped(values: BTreeSet<Platter>) -> Self {
+        match values.len() <= MAX_VALUES {
+            true => Self::Known(values),
+            false => Self::Unknown,
+        }
+    }
+    fn union(&self, other: &Self) -> Self {
+        match (self, other) {
+            (Self::Known(a), Self::Known(b)) => Self::capped(a.union(b).copied().collect()),
+            _ => Self::Unknown,
+        }
+    }
+    /// `f` of every pair of values the registers may hold.
+    fn combine(&self, other: &Self, f: impl Fn(Platter, Platter) -> Option<Platter>) -> Self {
+        match (self, other) {
+            (Self::Known(a), Self::Known(b)) if a.len() * b.len() <= MAX_VALUES * MAX_VALUES => {
+                let values: Option<BTreeSet<Platter>> = a.iter().flat_map(|&x| b.iter().map(move |&y| (x, y))).map(|(x, y)| f(x, y)).collect();
+                values.map_or(Self::Unknown, Self::capped)
+            }
+            _ => Self::Unknown,
+        }
+    }
+    fn is(&self, p: Platter) -> bool {
+        matches!(self, Self::Known(values) if values.len() == 1 && values.contains(&p))
+    }
+}
+
+type State = [Value; NUMBER_OF_REGISTERS];
+
+/// How a basic block ends.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Exit {
+    /// Into the next block, which is also jumped to.
+    FallThrough,
+    /// A Load of array 0 to any of the successors.
+    Jump,
+    /// A Load of array 0 to an offset that is not known.
+    Indirect,
+    /// A Load of an array that may not be array 0.
+    Load,
+    Halt,
+    /// A platter that is not an instruction, or the end of array 0.
+    Invalid,
+}
+
+/// A run of instructions that is only entered at its first and only left
+/// after its last.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Block {
+    pub start: usize,
+    /// The offset of the last instruction.
+    pub end: usize,
+    pub exit: Exit,
+    /// The starts of the blocks control may go to next.
+    pub successors: Vec<usize>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Cfg {
+    /// The blocks reachable from offset 0, in order of their starts.
+    pub blocks: Vec<Block>,
+    platters: Vec<Platter>,
+    lines: Vec<Line>,
+}
+
+impl Cfg {
+    pub fn new(program: &Program) -> Self {
+        let platters = program.as_slice();
+        let decode = |finger: usize| platters.get(finger).and_then(|&p| Decoded::new(p));
+        let mut states: BTreeMap<usize, State> = BTreeMap::new();
+        // Where each reachable Load goes, if it is a jump.
+        let mut jumps: BTreeMap<usize, Value> = BTreeMap::new();
+        let mut work = VecDeque::from([0]);
+        states.insert(0, std::array::from_fn(|_| Value::constant(0)));
+        while let Some(finger) = work.pop_front() {
+            let Some(i) = decode(finger) else { continue };
+            let mut state = states[&finger].clone();
+            let successors: Vec<usize> = match i.op {
+                Op::Halt => vec![],
+                Op::Load if state[index(i.b)].is(0) => {
+                    let target = state[index(i.c)].clone();
+                    jumps.insert(finger, target.clone());
+                    match target {
+                        Value::Known(targets) => targets.into_iter().map(|t| t as usize).collect(),
+                        Value::Unknown => vec![],
+                    }
+                }
+                Op::Load => vec![],
+                _ => {
+                    step(&mut state, i);
+                    vec![finger + 1]
+                }
+            };
+            for next in successors {
+                if next >= platters.len() {
+                    continue;
+                }
+                let merged = match states.get(&next) {
+                    Some(old) => std::array::from_fn(|k| old[k].union(&state[k])),
+                    None => state.clone(),
+                };
+                if states.get(&next) != Some(&merged) {
+                    states.insert(next, merged);
+                    work.push_back(next);
+                }
+            }
+        }
+        let mut leaders: BTreeSet<usize> = BTreeSet::from([0]);
+        for target in jumps.values() {
+            if let Value::Known(targets) = target {
+                leaders.extend(targets.iter().map(|&t| t as usize).filter(|&t| states.contains_key(&t)));
+            }
+        }
+        let mut blocks = Vec::new();
+        let mut fingers = states.keys().copied().peekable();
+        while let Some(start) = fingers.next() {
+            let mut end = start;
+            while fingers.peek() == Some(&(end + 1)) && !leaders.contains(&(end + 1)) && !ends(decode(end)) {
+                end += 1;
+                fingers.next();
+            }
+            let (exit, successors) = match decode(end).map(|i| i.op) {
+                None => (Exit::Invalid, vec![]),
+                Some(Op::Halt) => (Exit::Halt, vec![]),
+                Some(Op::Load) => match jumps.get(&end) {
+                    Some(Value::Known(targets)) => (
+                        Exit::Jump,
+                        targets.iter().map(|&t| t as usize).filter(|t| states.contains_key(t)).collect(),
+                    ),
+                    Some(Value::Unknown) => (Exit::Indirect, vec![]),
+                    None => (Exit::Load, vec![]),
+                },
+                Some(_) if states.contains_key(&(end + 1)) => (Exit::FallThrough, vec![end + 1]),
+                Some(_) => (Exit::Invalid, vec![]),
+            };
+            blocks.push(Block { start, end, exit, successors });
+        }
+        Self {
+            blocks,
+            platters: platters.to_vec(),
+            lines: disasm::lines(program, false),
+        }
+    }
+    /// The instructions of `block`, disassembled a line each.
+    pub fn disassemble(&self, block: &Block) -> Vec<String> {
+        self.lines[block.start..=block.end].iter().map(Line::to_string).collect()
+    }
+    pub fn write_dot(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "digraph cfg {{")?;
+        writeln!(out, "    node [shape=box, fontname=monospace];")?;
+        for block in &self.blocks {
+            let mut label = String::new();
+            for (k, text) in self.disassemble(block).iter().enumerate() {
+                label += &format!("{:08x}: {}\\l", block.start + k, text.replace('\\', "\\\\").replace('"', "\\\""));
+            }
+            match block.exit {
+                Exit::Indirect => label += "(indirect jump)\\l",
+                Exit::Load => label += "(loads another array)\\l",
+                Exit::Invalid => label += "(invalid)\\l",
+                _ => {}
+            }
+            writeln!(out, "    b{} [label=\"{label}\"];", block.start)?;
+            for next in &block.successors {
+                let style = match block.exit {
+                    Exit::FallThrough => " [style=dashed]",
+                    _ => "",
+                };
+                writeln!(out, "    b{} -> b{next}{style};", block.start)?;
+            }
+        }
+        writeln!(out, "}}")
+    }
+    pub fn write_json(&self, out: &mut impl Write) -> io::Result<()> {
+        let blocks = self.blocks.iter().map(|block| {
+            let instructions = self
+                .disassemble(block)
+                .into_iter()
+                .enumerate()
+                .map(|(k, text)| {
+                    let offset = block.start + k;
+                    Json::object([
+                        ("offset", offset.into()),
+                        ("platter", u64::from(self.platters[offset]).into()),
+                        ("text", text.into()),
+                    ])
+                })
+                .collect::<Vec<_>>();
+            let exit = match block.exit {
+                Exit::FallThrough => "fallthrough",
+                Exit::Jump => "jump",
+                Exit::Indirect => "indirect",
+                Exit::Load => "load",
+                Exit::Halt => "halt",
+                Exit::Invalid => "invalid",
+            };
+            Json::object([
+                ("start", block.start.into()),
+                ("end", block.end.into()),
+                ("exit", exit.into()),
+                ("successors", block.successors.iter().map(|&s| s.into()).collect::<Vec<Json>>().into()),
+                ("instructions", instructions.into()),
+            ])
+        });
+        writeln!(out, "{}", Json::object([("blocks", blocks.collect::<Vec<_>>().into())]))
+    }
+}
+
+/// Whether control may not go on to the next platter after `i`.
+fn ends(i: Option<Decoded>) -> bool {
+    i.is_none_or(|i| matches!(i.op, Op::Load | Op::Halt))
+}
+
+fn index(i: Index) -> usize {
+    i.into()
+}
+
+/// What the registers may hold after `i`, which is not a Load or a Halt.
+fn step(state: &mut State, i: Decoded) {
+    let (a, b, c) = (index(i.a), index(i.b), index(i.c));
+    match i.op {
+        Op::Move if state[c].is(0) => {}
+        Op::Move if matches!(&state[c], Value::Known(values) if !values.contains(&0)) => state[a] = state[b].clone(),
+        Op::Move => state[a] = state[a].union(&state[b]),
+        Op::Add => state[a] = state[b].combine(&state[c], |x, y| Some(x.wrapping_add(y))),
+        Op::Mult => state[a] = state[b].combine(&state[c], |x, y| Some(x.wrapping_mul(y))),
+        Op::Div => state[a] = state[b].combine(&state[c], |x, y| x.checked_div(y)),
+        Op::NotAnd => state[a] = state[b].combine(&state[c], |x, y| Some(!(x & y))),
+        Op::Orth => state[a] = Value::constant(i.value),
+        Op::Index => state[a] = Value::Unknown,
+        Op::Alloc => state[b] = Value::Unknown,
+        Op::Input => state[c] = Value::Unknown,
+        Op::Amend | Op::Aband | Op::Output | Op::Load | Op::Halt => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    fn cfg(source: &str) -> Cfg {
+        Cfg::new(&assemble(source).unwrap())
+    }
+
+    fn summary(cfg: &Cfg) -> Vec<(usize, usize, Exit, Vec<usize>)> {
+        cfg.blocks.iter().map(|b| (b.start, b.end, b.exit, b.successors.clone())).collect()
+    }
+
+    #[test]
+    fn blocks() {
+        let cfg = cfg("
+                  in r4
+                  orth r1, then
+                  orth r2, else
+                  cmov r1, r2, r4
+                  load r0, r1
+            then: out r1
+                  load r0, r2
+            else: load r0, r4
+                  .data 0xffffffff
+        ");
+        assert_eq!(summary(&cfg), [
+            (0, 4, Exit::Jump, vec![5, 7]),
+            (5, 6, Exit::Jump, vec![7]),
+            (7, 7, Exit::Indirect, vec![]),
+        ]);
+        assert_eq!(cfg.disassemble(&cfg.blocks[1]), ["out r1", "load r0, r2"]);
+    }
+
+    #[test]
+    fn export() {
+        let cfg = cfg("
+                  orth r1, next
+                  load r0, r1
+            next: orth r3, 1
+                  load r3, r1
+        ");
+        assert_eq!(summary(&cfg), [(0, 1, Exit::Jump, vec![2]), (2, 3, Exit::Load, vec![])]);
+        let mut dot = Vec::new();
+        cfg.write_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.contains("    b0 [label=\"00000000: orth r1, 0x2\\l00000001: load r0, r1\\l\"];\n    b0 -> b2;\n"));
+        assert!(dot.contains("(loads another array)"));
+        let mut json = Vec::new();
+        cfg.write_json(&mut json).unwrap();
+        let json = Json::parse(std::str::from_utf8(&json).unwrap()).unwrap();
+        let blocks = json.get("blocks").and_then(Json::as_array).unwrap();
+        assert_eq!(blocks[1].get("exit"), Some(&"load".into()));
+        let instructions = blocks[0].get("instructions").and_then(Json::as_array).unwrap();
+        assert_eq!(instructions[1].get("text"), Some(&"load r0, r1".into()));
+        assert_eq!(instructions[0].get("platter").and_then(Json::as_u64), Some(0xd200_0002));
+    }
+
+    #[test]
+    fn loops() {
+        // A counter that is added to in a loop, so its value is not known.
+        let cfg = cfg("
+                  orth r1, 1
+                  orth r2, loop
+            loop: add r3, r3, r1
+                  out r3
+                  load r0, r2
+        ");
+        assert_eq!(summary(&cfg), [(0, 1, Exit::FallThrough, vec![2]), (2, 4, Exit::Jump, vec![2])]);
+    }
+}
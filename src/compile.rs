@@ -0,0 +1,228 @@
+//! Translates a program into the Rust source of a standalone binary.
+//!
+//! Each basic block of array 0 becomes a function of straight-line code,
+//! which can be entered at any of its instructions. The compiled code hands
+//! the machine over to a `Machine` for good when it reaches something it
+//! does not do itself:
+//!
+//! - an instruction that would fault, or Halt, so that the `Machine` fails
+//!   or halts on it
+//! - a Load of an array other than '0'
+//! - a block in which an instruction that may run was amended
+//! - a finger outside every block
+//!
+//! The output depends on this crate, e.g. as a file in `examples/`.
+
+use std::io::{self, Write};
+use crate::{
+    decode::Decoded,
+    instruction::Instruction,
+    memory::Platter,
+    op::Op,
+    program::Program,
+};
+
+/// The most instructions in one block, to keep functions small enough for
+/// the Rust compiler.
+const MAX_BLOCK: usize = 256;
+
+/// A run of valid instructions of array 0 that ends in a Load or a Halt, or
+/// before the first platter that is not an instruction.
+#[derive(Debug, PartialEq, Clone)]
+struct Block {
+    start: usize,
+    instructions: Vec<Decoded>,
+}
+
+impl Block {
+    fn end(&self) -> usize {
+        self.start + self.instructions.len() - 1
+    }
+}
+
+fn blocks(program: &[Platter]) -> Vec<Block> {
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut open = false;
+    for (finger, &platter) in program.iter().enumerate() {
+        let Some(i) = Decoded::new(platter) else {
+            open = false;
+            continue;
+        };
+        match blocks.last_mut() {
+            Some(block) if open => block.instructions.push(i),
+            _ => blocks.push(Block { start: finger, instructions: vec![i] }),
+        }
+        let len = blocks.last().map_or(0, |b| b.instructions.len());
+        open = !matches!(i.op, Op::Load | Op::Halt) && len < MAX_BLOCK;
+    }
+    blocks
+}
+
+/// Writes Rust source running `program`, which was read from `name`.
+pub fn compile(program: &Program, name: &str, out: &mut impl Write) -> io::Result<()> {
+    let platters = program.as_slice();
+    let blocks = blocks(platters);
+    writeln!(out, "//! Compiled from {name} by `um compile`.")?;
+    out.write_all(PRELUDE.as_bytes())?;
+    writeln!(out, "\nstatic PROGRAM: [Platter; {}] = [", platters.len())?;
+    for row in platters.chunks(8) {
+        let row: Vec<String> = row.iter().map(|p| format!("{p:#010x}")).collect();
+        writeln!(out, "    {},", row.join(", "))?;
+    }
+    writeln!(out, "];\n\n/// The first and last offset of every block.")?;
+    writeln!(out, "static BLOCKS: [(usize, usize); {}] = [", blocks.len())?;
+    for block in &blocks {
+        writeln!(out, "    ({}, {}),", block.start, block.end())?;
+    }
+    writeln!(out, "];\n\nfn dispatch(s: &mut State, finger: usize) -> Result<usize, usize> {{")?;
+    writeln!(out, "    match finger {{")?;
+    for (n, block) in blocks.iter().enumerate() {
+        writeln!(out, "        {}..={} => block_{n}(s, finger),", block.start, block.end())?;
+    }
+    writeln!(out, "        _ => Err(finger),\n    }}\n}}")?;
+    for (n, block) in blocks.iter().enumerate() {
+        writeln!(out, "\nfn block_{n}(s: &mut State, entry: usize) -> Result<usize, usize> {{")?;
+        writeln!(out, "    if s.amended[{n}].is_some_and(|offset| entry <= offset) {{")?;
+        writeln!(out, "        return Err(entry);\n    }}")?;
+        for (k, &i) in block.instructions.iter().enumerate() {
+            let finger = block.start + k;
+            let text = Instruction::try_from(platters[finger]).map(|i| i.to_string()).unwrap_or_default();
+            writeln!(out, "    // {finger}: {text}")?;
+            writeln!(out, "    if entry <= {finger} {{")?;
+            for line in statements(i, finger) {
+                writeln!(out, "        {line}")?;
+            }
+            writeln!(out, "    }}")?;
+        }
+        writeln!(out, "    Ok({})\n}}", block.end() + 1)?;
+    }
+    Ok(())
+}
+
+/// Rust statements for the instruction `i` at `finger`.
+fn statements(i: Decoded, finger: usize) -> Vec<String> {
+    let number = |i| Into::<usize>::into(i);
+    let (a, b, c) = (number(i.a), number(i.b), number(i.c));
+    let fail = format!("return Err({finger})");
+    match i.op {
+        Op::Move => vec![format!("if s.r[{c}] != 0 {{ s.r[{a}] = s.r[{b}]; }}")],
+        Op::Index => vec![format!(
+            "s.r[{a}] = match s.memory.get(s.r[{b}].into(), s.r[{c}]) {{ Ok(p) => p, Err(_) => {fail} }};"
+        )],
+        Op::Amend => vec![
+            format!("if s.memory.set(s.r[{a}].into(), s.r[{b}], s.r[{c}]).is_err() {{ {fail}; }}"),
+            format!("if s.r[{a}] == 0 && s.amend(s.r[{b}] as usize) {{ return Ok({}); }}", finger + 1),
+        ],
+        Op::Add => vec![format!("s.r[{a}] = s.r[{b}].wrapping_add(s.r[{c}]);")],
+        Op::Mult => vec![format!("s.r[{a}] = s.r[{b}].wrapping_mul(s.r[{c}]);")],
+        Op::Div => vec![
+            format!("if s.r[{c}] == 0 {{ {fail}; }}"),
+            format!("s.r[{a}] = s.r[{b}] / s.r[{c}];"),
+        ],
+        Op::NotAnd => vec![format!("s.r[{a}] = !(s.r[{b}] & s.r[{c}]);")],
+        Op::Halt => vec![fail + ";"],
+        Op::Alloc => vec![format!(
+            "s.r[{b}] = match s.memory.alloc(s.r[{c}] as usize) {{ Ok(array) => array.into(), Err(_) => {fail} }};"
+        )],
+        Op::Aband => vec![format!("if s.memory.free(s.r[{c}].into()).is_err() {{ {fail}; }}")],
+        Op::Output => vec![format!(
+            "if s.r[{c}] > 255 || s.console.write_byte(s.r[{c}] as u8).is_err() {{ {fail}; }}"
+        )],
+        Op::Input => vec![format!(
+            "s.r[{c}] = match s.console.flush().and_then(|()| s.console.read_byte()) {{ Ok(byte) => byte.map_or(0xffff_ffff, Platter::from), Err(_) => {fail} }};"
+        )],
+        Op::Load => vec![
+            format!("if s.r[{b}] != 0 {{ {fail}; }}"),
+            format!("return Ok(s.r[{c}] as usize);"),
+        ],
+        Op::Orth => vec![format!("s.r[{a}] = {:#x};", i.value)],
+    }
+}
+
+/// What every compiled program starts with.
+const PRELUDE: &str = r#"//! Build it as a binary that depends on the `um` crate.
+
+use um::{snapshot::Snapshot, Console, Machine, Memory, Platter, Register, Stdio};
+
+struct State {
+    r: [Platter; 8],
+    memory: Memory,
+    console: Stdio,
+    /// For every block, the last offset in it amended so far.
+    amended: Vec<Option<usize>>,
+}
+
+impl State {
+    /// Notes that array 0 was amended at `offset`, and returns whether that
+    /// changed a block.
+    #[allow(dead_code)]
+    fn amend(&mut self, offset: usize) -> bool {
+        let n = BLOCKS.partition_point(|&(start, _)| start <= offset);
+        match n.checked_sub(1) {
+            Some(n) if offset <= BLOCKS[n].1 => {
+                self.amended[n] = Some(self.amended[n].map_or(offset, |o| o.max(offset)));
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+fn main() {
+    let mut memory = Memory::new();
+    memory.load_program(PROGRAM.to_vec().into());
+    let mut s = State { r: [0; 8], memory, console: Stdio::new(), amended: vec![None; BLOCKS.len()] };
+    let mut finger = 0;
+    let finger = loop {
+        match dispatch(&mut s, finger) {
+            Ok(next) => finger = next,
+            Err(at) => break at,
+        }
+    };
+    let mut machine = Machine::with_console(s.console);
+    let registers: [Register; 8] = s.r.map(Register::from);
+    machine.restore(Snapshot { finger, registers: registers.into(), memory: s.memory });
+    if let Err(fault) = machine.run() {
+        eprintln!("The machine failed: {fault}");
+        std::process::exit(1);
+    }
+}
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    #[test]
+    fn basic_blocks() {
+        let program = assemble("
+            orth r1, 3
+            load r0, r1
+            .data 0xffffffff
+            out r1
+            halt
+            add r1, r2, r3
+        ").unwrap();
+        let starts: Vec<(usize, usize)> = blocks(program.as_slice()).iter().map(|b| (b.start, b.end())).collect();
+        assert_eq!(starts, [(0, 1), (3, 4), (5, 5)]);
+        let long = assemble(&"add r1, r2, r3\n".repeat(MAX_BLOCK + 1)).unwrap();
+        assert_eq!(blocks(long.as_slice()).len(), 2);
+    }
+
+    #[test]
+    fn source() {
+        let program = assemble("
+            orth r1, 0x41
+            out r1
+            halt
+        ").unwrap();
+        let mut out = Vec::new();
+        compile(&program, "hello.umz", &mut out).unwrap();
+        let source = String::from_utf8(out).unwrap();
+        assert!(source.starts_with("//! Compiled from hello.umz by `um compile`.\n"));
+        assert!(source.contains("static BLOCKS: [(usize, usize); 1] = [\n    (0, 2),\n];"));
+        assert!(source.contains("        0..=2 => block_0(s, finger),\n"));
+        assert!(source.contains("    // 1: out r1\n    if entry <= 1 {\n        if s.r[1] > 255 || s.console.write_byte(s.r[1] as u8).is_err() { return Err(1); }\n    }\n"));
+    }
+}
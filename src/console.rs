@@ -0,0 +1,174 @@
+use std::{collections::VecDeque, io::{self, BufWriter, Read, Stdout, Write}, path::Path};
+
+/// Where `Op::Input` reads from and `Op::Output` writes to.
+pub trait Console {
+    /// The next byte of input, or `None` once the end of input has been
+    /// signaled.
+    fn read_byte(&mut self) -> io::Result<Option<u8>>;
+    fn write_byte(&mut self, byte: u8) -> io::Result<()>;
+    /// Makes everything written so far visible. The machine flushes before
+    /// it waits for input and when it halts or Fails.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The console of the host process: standard input and standard output.
+/// Output is buffered until the console is flushed.
+#[derive(Debug)]
+pub struct Stdio {
+    out: BufWriter<Stdout>,
+}
+
+impl Stdio {
+    pub fn new() -> Self {
+        Self {
+            out: BufWriter::new(io::stdout()),
+        }
+    }
+}
+
+impl Default for Stdio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Console for Stdio {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        read_byte(&mut io::stdin())
+    }
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.out.write_all(&[byte])
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// An in-memory console. Input is consumed from the front of `input` and
+/// output is appended to `output`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Buffer {
+    pub input: VecDeque<u8>,
+    pub output: Vec<u8>,
+}
+
+impl Buffer {
+    pub fn new(input: impl Into<VecDeque<u8>>) -> Self {
+        Self {
+            input: input.into(),
+            output: Vec::new(),
+        }
+    }
+}
+
+impl Console for Buffer {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.input.pop_front())
+    }
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.output.push(byte);
+        Ok(())
+    }
+}
+
+/// Feeds a script to the machine before handing input over to another
+/// console, e.g. to log in to UMIX and then continue interactively.
+#[derive(Debug)]
+pub struct Scripted<C> {
+    script: VecDeque<u8>,
+    inner: C,
+}
+
+impl<C: Console> Scripted<C> {
+    pub fn new(script: impl Into<VecDeque<u8>>, inner: C) -> Self {
+        Self {
+            script: script.into(),
+            inner,
+        }
+    }
+    pub fn from_file(path: impl AsRef<Path>, inner: C) -> io::Result<Self> {
+        Ok(Self::new(std::fs::read(path)?, inner))
+    }
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+}
+
+impl<C: Console> Console for Scripted<C> {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        match self.script.pop_front() {
+            Some(byte) => Ok(Some(byte)),
+            None => self.inner.read_byte(),
+        }
+    }
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.inner.write_byte(byte)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Stops the machine when input ends, instead of signaling the end of input
+/// to the program. Reading fails with `io::ErrorKind::WouldBlock`, which
+/// `Machine::run_limited` reports as `RunOutcome::WaitingForInput` with the
+/// execution finger left on the Input instruction, so the machine can be
+/// saved and resumed later with more input.
+#[derive(Debug, Default)]
+pub struct StopAtEof<C>(pub C);
+
+impl<C: Console> Console for StopAtEof<C> {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        self.0.read_byte()?.map(Some).ok_or(io::ErrorKind::WouldBlock.into())
+    }
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.0.write_byte(byte)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+fn read_byte(reader: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut buf = [0u8; 1];
+    loop {
+        match reader.read(&mut buf) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(buf[0])),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffer_echo() {
+        let mut c = Buffer::new(b"hi".to_vec());
+        while let Some(byte) = c.read_byte().unwrap() {
+            c.write_byte(byte).unwrap();
+        }
+        assert_eq!(c.output, b"hi");
+        assert_eq!(c.read_byte().unwrap(), None);
+    }
+
+    #[test]
+    fn script_before_inner() {
+        let mut c = Scripted::new(b"ab".to_vec(), Buffer::new(b"c".to_vec()));
+        let mut got = Vec::new();
+        while let Some(byte) = c.read_byte().unwrap() {
+            got.push(byte);
+        }
+        assert_eq!(got, b"abc");
+        c.write_byte(b'x').unwrap();
+        assert_eq!(c.into_inner().output, b"x");
+    }
+}
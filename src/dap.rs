@@ -0,0 +1,514 @@
+//! A Debug Adapter Protocol server, so that editors like VS Code can debug a
+//! program: `um dap` speaks the protocol on standard input and output.
+//!
+//! `editors/vscode` registers the debugger with VS Code.
+//!
+//! The source is array 0, disassembled a platter to a line, so line N is
+//! the platter at offset N - 1. It is named `array 0.uma` and fetched with
+//! a `source` request, and its reference changes whenever a Load replaces
+//! array 0, after which the old reference is refused. The scopes are the
+//! registers and every active array, one page of platters at a time.
+//!
+//! The `launch` arguments are `program`, the file to debug, `input`, a file
+//! its input is read from, and `stopOnEntry`. Without an `input` file the
+//! program reads the end of input, which the editor is told about the first
+//! time. Its output is sent as `output` events. `next` and `stepIn` run one
+//! instruction, and `stepOut` is refused, as there is only ever one frame.
+
+use std::{
+    collections::BTreeSet,
+    io::{self, BufRead, Write},
+    sync::mpsc::{self, Receiver, TryRecvError},
+};
+use crate::{
+    console::Buffer,
+    disasm,
+    json::Json,
+    machine::{Machine, StepOutcome},
+    memory::{MemoryAddress, Platter},
+    op::Op,
+    program::Program,
+    register::NUMBER_OF_REGISTERS,
+};
+
+/// How many instructions a running program executes between looks for a
+/// `pause`.
+const SLICE: usize = 1 << 16;
+const THREAD: u64 = 1;
+/// Variable references for the scopes. Array N is `FIRST_ARRAY + N`.
+const REGISTERS: u64 = 1;
+const ARRAYS: u64 = 2;
+const FIRST_ARRAY: u64 = 3;
+
+/// Answers requests from `input` on `output` until the editor disconnects.
+/// Requests are read on another thread, so that a running program can be
+/// paused.
+pub fn serve(input: impl BufRead + Send + 'static, output: impl Write) -> io::Result<()> {
+    let (send, requests) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut input = input;
+        while let Ok(Some(message)) = receive(&mut input) {
+            if send.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    Session::new(output, requests).serve()
+}
+
+/// Reads one `Content-Length` framed message, or `None` at the end of input.
+fn receive(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() && length.is_some() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    let text = String::from_utf8_lossy(&body);
+    Json::parse(&text).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+struct Session<W> {
+    out: W,
+    requests: Receiver<Json>,
+    seq: u64,
+    machine: Option<Machine<Buffer>>,
+    /// Fingers to stop at.
+    breakpoints: BTreeSet<usize>,
+    stop_on_entry: bool,
+    configured: bool,
+    running: bool,
+    /// Whether to tell the editor when the program first reads input, as
+    /// no `input` file was given.
+    warn_input: bool,
+    /// The reference of the source, counting the Loads that replaced
+    /// array 0.
+    source: u64,
+}
+
+impl<W: Write> Session<W> {
+    fn new(out: W, requests: Receiver<Json>) -> Self {
+        Self {
+            out,
+            requests,
+            seq: 0,
+            machine: None,
+            breakpoints: BTreeSet::new(),
+            stop_on_entry: false,
+            configured: false,
+            running: false,
+            warn_input: false,
+            source: 1,
+        }
+    }
+    fn serve(&mut self) -> io::Result<()> {
+        loop {
+            let request = match self.running {
+                true => match self.requests.try_recv() {
+                    Ok(request) => Some(request),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                },
+                false => match self.requests.recv() {
+                    Ok(request) => Some(request),
+                    Err(_) => return Ok(()),
+                },
+            };
+            if let Some(request) = request {
+                if !self.handle(&request)? {
+                    return Ok(());
+                }
+            }
+            if self.running {
+                self.run(SLICE)?;
+            }
+        }
+    }
+    /// Answers `request`, and returns whether to go on.
+    fn handle(&mut self, request: &Json) -> io::Result<bool> {
+        let command = request.get("command").and_then(Json::as_str).unwrap_or_default();
+        let arguments = request.get("arguments").cloned().unwrap_or(Json::Null);
+        let body = match command {
+            "initialize" => Ok(Json::object([
+                ("supportsConfigurationDoneRequest", true.into()),
+                ("supportsTerminateRequest", true.into()),
+            ])),
+            "launch" => self.launch(&arguments),
+            "setBreakpoints" => Ok(self.set_breakpoints(&arguments)),
+            "setExceptionBreakpoints" => Ok(Json::Null),
+            "configurationDone" => {
+                self.configured = true;
+                Ok(Json::Null)
+            }
+            "threads" => Ok(Json::object([(
+                "threads",
+                vec![Json::object([("id", THREAD.into()), ("name", "um".into())])].into(),
+            )])),
+            "stackTrace" => self.stack_trace(),
+            "source" => self.source_text(&arguments),
+            "scopes" => Ok(Json::object([("scopes", vec![
+                Json::object([("name", "Registers".into()), ("variablesReference", REGISTERS.into()), ("expensive", false.into())]),
+                Json::object([("name", "Arrays".into()), ("variablesReference", ARRAYS.into()), ("expensive", false.into())]),
+            ].into())])),
+            "variables" => self.variables(&arguments),
+            "continue" => {
+                self.running = self.machine.is_some();
+                Ok(Json::object([("allThreadsContinued", true.into())]))
+            }
+            "next" | "stepIn" => Ok(Json::Null),
+            "pause" => Ok(Json::Null),
+            "disconnect" | "terminate" => {
+                self.respond(request, Ok(Json::Null))?;
+                if command == "terminate" {
+                    self.event("terminated", Json::Null)?;
+                }
+                return Ok(false);
+            }
+            _ => Err(format!("`{command}` is not supported")),
+        };
+        let launched = command == "launch" && body.is_ok();
+        self.respond(request, body)?;
+        match command {
+            "initialize" => self.event("initialized", Json::Null)?,
+            "next" | "stepIn" if self.machine.is_some() => {
+                self.running = false;
+                self.run(1)?;
+            }
+            "pause" if self.running => {
+                self.running = false;
+                self.stopped("pause", None)?;
+            }
+            _ => {}
+        }
+        if (launched || command == "configurationDone") && self.configured && self.machine.is_some() {
+            match self.stop_on_entry {
+                true => self.stopped("entry", None)?,
+                false => self.running = true,
+            }
+        }
+        Ok(true)
+    }
+    fn launch(&mut self, arguments: &Json) -> Result<Json, String> {
+        let path = arguments.get("program").and_then(Json::as_str).ok_or("`program` is missing")?;
+        let program = std::fs::read(path).map_err(|e| format!("Could not read {path}: {e}"))?;
+        let input = match arguments.get("input").and_then(Json::as_str) {
+            Some(input) => std::fs::read(input).map_err(|e| format!("Could not read {input}: {e}"))?,
+            None => Vec::new(),
+        };
+        self.warn_input = arguments.get("input").is_none();
+        let mut machine = Machine::with_console(Buffer::new(input));
+        machine.load(Program::from(program));
+        self.machine = Some(machine);
+        self.stop_on_entry = arguments.get("stopOnEntry").and_then(Json::as_bool).unwrap_or(false);
+        Ok(Json::Null)
+    }
+    fn set_breakpoints(&mut self, arguments: &Json) -> Json {
+        self.breakpoints.clear();
+        let lines = arguments.get("breakpoints").and_then(Json::as_array).unwrap_or_default();
+        let verified = lines
+            .iter()
+            .filter_map(|b| b.get("line").and_then(Json::as_u64))
+            .map(|line| {
+                let verified = line > 0;
+                if verified {
+                    self.breakpoints.insert(line as usize - 1);
+                }
+                Json::object([("verified", verified.into()), ("line", line.into())])
+            })
+            .collect::<Vec<_>>();
+        Json::object([("breakpoints", verified.into())])
+    }
+    fn stack_trace(&self) -> Result<Json, String> {
+        let machine = self.machine.as_ref().ok_or("no program was launched")?;
+        let finger = machine.finger();
+        let name = match machine.peek() {
+            Ok(i) => i.to_string(),
+            Err(fault) => fault.kind.to_string(),
+        };
+        let frame = Json::object([
+            ("id", 0u64.into()),
+            ("name", name.into()),
+            ("source", Json::object([("name", "array 0.uma".into()), ("sourceReference", self.source.into())])),
+            ("line", (finger + 1).into()),
+            ("column", 1u64.into()),
+        ]);
+        Ok(Json::object([("stackFrames", vec![frame].into()), ("totalFrames", 1u64.into())]))
+    }
+    fn source_text(&self, arguments: &Json) -> Result<Json, String> {
+        let machine = self.machine.as_ref().ok_or("no program was launched")?;
+        let reference = arguments
+            .get("source")
+            .and_then(|source| source.get("sourceReference"))
+            .or_else(|| arguments.get("sourceReference"))
+            .and_then(Json::as_u64)
+            .ok_or("`sourceReference` is missing")?;
+        if reference != self.source {
+            return Err(format!("source {reference} is gone, a Load replaced array 0 since"));
+        }
+        let program = Program::from(machine.memory().array(0.into()).map_err(|kind| kind.to_string())?.clone());
+        let mut text = Vec::new();
+        disasm::disassemble(&program, &disasm::Options::default(), &mut text).map_err(|e| e.to_string())?;
+        Ok(Json::object([("content", String::from_utf8_lossy(&text).into_owned().into())]))
+    }
+    fn variables(&self, arguments: &Json) -> Result<Json, String> {
+        let machine = self.machine.as_ref().ok_or("no program was launched")?;
+        let reference = arguments.get("variablesReference").and_then(Json::as_u64).unwrap_or(0);
+        let variable = |name: String, value: String, reference: u64| {
+            Json::object([("name", name.into()), ("value", value.into()), ("variablesReference", reference.into())])
+        };
+        let mut variables = Vec::new();
+        match reference {
+            REGISTERS => {
+                let r = machine.registers();
+                for i in 0..NUMBER_OF_REGISTERS as u32 {
+                    let value: u32 = r[i.into()].into();
+                    variables.push(variable(format!("r{i}"), format!("{value:#010x} ({value})"), 0));
+                }
+                variables.push(variable("finger".to_string(), machine.finger().to_string(), 0));
+            }
+            ARRAYS => {
+                for (id, array) in machine.memory().slots().enumerate() {
+                    let Some(array) = array else { continue };
+                    let mut v = variable(id.to_string(), format!("{} platters", array.len()), FIRST_ARRAY + id as u64);
+                    if let Json::Object(members) = &mut v {
+                        members.push(("indexedVariables".to_string(), array.len().into()));
+                    }
+                    variables.push(v);
+                }
+            }
+            reference => {
+                let id = Platter::try_from(reference.saturating_sub(FIRST_ARRAY)).map_err(|e| e.to_string())?;
+                let array: MemoryAddress = id.into();
+                let platters = machine.memory().array(array).map_err(|kind| kind.to_string())?.as_slice();
+                let start = arguments.get("start").and_then(Json::as_u64).unwrap_or(0) as usize;
+                let count = arguments.get("count").and_then(Json::as_u64).map_or(platters.len(), |c| c as usize);
+                let end = start.saturating_add(count).min(platters.len());
+                for (offset, p) in platters.iter().enumerate().take(end).skip(start) {
+                    variables.push(variable(format!("[{offset}]"), format!("{p:#010x}"), 0));
+                }
+            }
+        }
+        Ok(Json::object([("variables", variables.into())]))
+    }
+    /// Executes up to `n` instructions, stopping at breakpoints, and tells
+    /// the editor when the machine stops.
+    fn run(&mut self, n: usize) -> io::Result<()> {
+        let Some(machine) = &mut self.machine else { return Ok(()) };
+        let mut outcome = Ok(StepOutcome::Running);
+        let mut breakpoint = false;
+        let mut reads = false;
+        for _ in 0..n {
+            let loads = machine.peek().is_ok_and(|i| i.op == Op::Load && machine.registers()[i.b] != 0.into());
+            reads |= machine.peek().is_ok_and(|i| i.op == Op::Input);
+            outcome = machine.step();
+            if loads && outcome.is_ok() {
+                self.source += 1;
+            }
+            breakpoint = self.breakpoints.contains(&machine.finger());
+            if outcome != Ok(StepOutcome::Running) || breakpoint {
+                break;
+            }
+        }
+        let output = std::mem::take(&mut machine.console_mut().output);
+        if reads && self.warn_input {
+            self.warn_input = false;
+            let text = "The program reads input, but no `input` file was given, so it reads the end of input.\n";
+            self.event("output", Json::object([("category", "console".into()), ("output", text.into())]))?;
+        }
+        if !output.is_empty() {
+            let text = String::from_utf8_lossy(&output).into_owned();
+            self.event("output", Json::object([("category", "stdout".into()), ("output", text.into())]))?;
+        }
+        let stepping = !self.running;
+        match outcome {
+            Ok(StepOutcome::Halted) => {
+                self.running = false;
+                self.event("exited", Json::object([("exitCode", 0u64.into())]))?;
+                self.event("terminated", Json::Null)?;
+            }
+            Err(fault) => {
+                self.running = false;
+                self.stopped("exception", Some(format!("The machine failed: {fault}")))?;
+            }
+            _ if breakpoint => {
+                self.running = false;
+                self.stopped("breakpoint", None)?;
+            }
+            _ if stepping => self.stopped("step", None)?,
+            _ => {}
+        }
+        Ok(())
+    }
+    fn stopped(&mut self, reason: &str, text: Option<String>) -> io::Result<()> {
+        let mut body = Json::object([
+            ("reason", reason.into()),
+            ("threadId", THREAD.into()),
+            ("allThreadsStopped", true.into()),
+        ]);
+        if let (Some(text), Json::Object(members)) = (text, &mut body) {
+            members.push(("text".to_string(), text.into()));
+        }
+        self.event("stopped", body)
+    }
+    fn respond(&mut self, request: &Json, body: Result<Json, String>) -> io::Result<()> {
+        let mut response = Json::object([
+            ("type", "response".into()),
+            ("request_seq", request.get("seq").cloned().unwrap_or(Json::Null)),
+            ("command", request.get("command").cloned().unwrap_or(Json::Null)),
+            ("success", body.is_ok().into()),
+        ]);
+        if let Json::Object(members) = &mut response {
+            match body {
+                Ok(Json::Null) => {}
+                Ok(body) => members.push(("body".to_string(), body)),
+                Err(message) => members.push(("message".to_string(), message.into())),
+            }
+        }
+        self.send(response)
+    }
+    fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        let mut message = Json::object([("type", "event".into()), ("event", event.into())]);
+        if let (Json::Object(members), false) = (&mut message, body == Json::Null) {
+            members.push(("body".to_string(), body));
+        }
+        self.send(message)
+    }
+    fn send(&mut self, message: Json) -> io::Result<()> {
+        self.seq += 1;
+        let Json::Object(mut members) = message else { unreachable!("messages are objects") };
+        members.insert(0, ("seq".to_string(), self.seq.into()));
+        let text = Json::Object(members).to_string();
+        write!(self.out, "Content-Length: {}\r\n\r\n{text}", text.len())?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(seq: u64, command: &str, arguments: Json) -> String {
+        let text = Json::object([
+            ("seq", seq.into()),
+            ("type", "request".into()),
+            ("command", command.into()),
+            ("arguments", arguments),
+        ])
+        .to_string();
+        format!("Content-Length: {}\r\n\r\n{text}", text.len())
+    }
+
+    /// Every message the server sent.
+    fn messages(out: &[u8]) -> Vec<Json> {
+        let mut out = out;
+        std::iter::from_fn(|| receive(&mut out).unwrap()).collect()
+    }
+
+    #[test]
+    fn session() {
+        // orth r1, 'A'; out r1; orth r2, 1; add r3, r1, r2; halt
+        let program = [0xd200_0041u32, 0xa000_0001, 0xd400_0001, 0x3000_00ca, 0x7000_0000];
+        let path = std::env::temp_dir().join(format!("um-dap-{}.um", std::process::id()));
+        std::fs::write(&path, program.iter().flat_map(|p| p.to_be_bytes()).collect::<Vec<u8>>()).unwrap();
+        let lines = |lines: &[u64]| -> Json {
+            let lines = lines.iter().map(|&line| Json::object([("line", line.into())])).collect::<Vec<_>>();
+            Json::object([("breakpoints", lines.into())])
+        };
+        let input = [
+            request(1, "initialize", Json::Null),
+            request(2, "launch", Json::object([
+                ("program", path.to_str().unwrap().into()),
+                ("stopOnEntry", true.into()),
+            ])),
+            request(3, "setBreakpoints", lines(&[4])),
+            request(4, "configurationDone", Json::Null),
+            request(5, "continue", Json::Null),
+            request(6, "stackTrace", Json::Null),
+            request(7, "variables", Json::object([("variablesReference", REGISTERS.into())])),
+            request(8, "variables", Json::object([("variablesReference", ARRAYS.into())])),
+            request(9, "variables", Json::object([
+                ("variablesReference", FIRST_ARRAY.into()),
+                ("start", 4u64.into()),
+                ("count", 10u64.into()),
+            ])),
+            request(10, "source", Json::object([("sourceReference", 1u64.into())])),
+            request(11, "source", Json::object([("sourceReference", 2u64.into())])),
+            request(12, "stepOut", Json::Null),
+            request(13, "next", Json::Null),
+            request(14, "next", Json::Null),
+            request(15, "evaluate", Json::Null),
+            request(16, "disconnect", Json::Null),
+        ]
+        .concat();
+        let mut out = Vec::new();
+        serve(io::Cursor::new(input.into_bytes()), &mut out).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let messages = messages(&out);
+        let events: Vec<String> = messages
+            .iter()
+            .map(|m| match m.get("type").and_then(Json::as_str) {
+                Some("event") => m.get("event").unwrap().as_str().unwrap().to_string(),
+                _ => m.get("command").unwrap().as_str().unwrap().to_string(),
+            })
+            .collect();
+        assert_eq!(events, [
+            "initialize", "initialized", "launch", "setBreakpoints", "configurationDone", "stopped",
+            "continue", "output", "stopped", "stackTrace", "variables", "variables", "variables", "source",
+            "source", "stepOut", "next", "stopped", "next", "exited", "terminated", "evaluate", "disconnect",
+        ]);
+        let body = |n: usize| messages[n].get("body").unwrap();
+        assert_eq!(body(5).get("reason"), Some(&"entry".into()));
+        assert_eq!(body(7).get("output"), Some(&"A".into()));
+        assert_eq!(body(8).get("reason"), Some(&"breakpoint".into()));
+        let frame = &body(9).get("stackFrames").unwrap().as_array().unwrap()[0];
+        assert_eq!(frame.get("line").and_then(Json::as_u64), Some(4));
+        assert_eq!(frame.get("name"), Some(&"add r3, r1, r2".into()));
+        let registers = body(10).get("variables").unwrap().as_array().unwrap();
+        assert_eq!(registers[1].get("value"), Some(&"0x00000041 (65)".into()));
+        assert_eq!(registers[8].get("value"), Some(&"3".into()));
+        let arrays = body(11).get("variables").unwrap().as_array().unwrap();
+        assert_eq!(arrays[0].get("value"), Some(&"5 platters".into()));
+        let platters = body(12).get("variables").unwrap().as_array().unwrap();
+        assert_eq!(platters.len(), 1);
+        assert_eq!(platters[0].get("value"), Some(&"0x70000000".into()));
+        let source = body(13).get("content").and_then(Json::as_str).unwrap();
+        assert_eq!(source.lines().nth(3), Some("00000003: 300000ca  add r3, r1, r2"));
+        assert_eq!(messages[14].get("success"), Some(&false.into()));
+        assert_eq!(messages[15].get("message"), Some(&"`stepOut` is not supported".into()));
+        assert_eq!(body(17).get("reason"), Some(&"step".into()));
+        assert_eq!(messages[21].get("success"), Some(&false.into()));
+    }
+
+    #[test]
+    fn input_without_file() {
+        let program = crate::asm::assemble("in r1\n in r1\n halt").unwrap();
+        let path = std::env::temp_dir().join(format!("um-dap-input-{}.um", std::process::id()));
+        std::fs::write(&path, program.to_bytes()).unwrap();
+        let input = [
+            request(1, "initialize", Json::Null),
+            request(2, "launch", Json::object([("program", path.to_str().unwrap().into())])),
+            request(3, "configurationDone", Json::Null),
+        ]
+        .concat();
+        let mut out = Vec::new();
+        serve(io::Cursor::new(input.into_bytes()), &mut out).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let warnings: Vec<Json> = messages(&out)
+            .into_iter()
+            .filter(|m| m.get("event") == Some(&"output".into()))
+            .filter_map(|m| m.get("body").cloned())
+            .collect();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].get("category"), Some(&"console".into()));
+    }
+}
@@ -0,0 +1,459 @@
+use std::{collections::BTreeMap, fmt, io::{self, BufRead, Write}};
+use crate::{
+    console::Console,
+    fault::{Fault, FaultKind},
+    journal::Journal,
+    machine::{Machine, StepOutcome},
+    memory::MemoryAddress,
+    op::Op,
+    watch::{Condition, Hit, Target, Watchpoint},
+};
+
+/// Where the debugger stops a running machine.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Breakpoint {
+    /// Before executing the instruction at this offset into array 0.
+    Finger(usize),
+    /// Before executing any instruction with this operator.
+    Op(Op),
+}
+
+/// Why the debugger handed control back.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Stop {
+    /// The requested number of instructions has been executed.
+    Stepped,
+    /// The breakpoint with this number was reached.
+    Breakpoint(usize),
+    Halted,
+    Watchpoint(Hit),
+    /// Running backwards, the first recorded instruction was undone.
+    Beginning,
+}
+
+/// Steps a machine under the control of breakpoints.
+pub struct Debugger<C> {
+    machine: Machine<C>,
+    breakpoints: BTreeMap<usize, Breakpoint>,
+    next_breakpoint: usize,
+}
+
+enum Flow {
+    Continue,
+    Quit,
+}
+
+const HELP: &str = "\
+step [N]            execute N instructions (default 1)
+continue            run until a breakpoint, halt or fault
+break FINGER        stop before the instruction at FINGER
+break op NAME       stop before any instruction with operator NAME
+delete N            remove breakpoint N
+breakpoints         list breakpoints
+watch WHAT [if CMP VALUE]
+                    stop after an instruction touches WHAT, which is a
+                    register (r0..r7), `read ARRAY OFFSET`, `write ARRAY
+                    OFFSET`, `alloc ARRAY` or `aband ARRAY`, and only if
+                    the new value compares (==, !=, <, <=, >, >=) to VALUE
+unwatch N           remove watchpoint N
+watchpoints         list watchpoints
+registers           print the registers and the execution finger
+dump ARRAY [START [COUNT]]
+                    print COUNT (default 16) platters of ARRAY from START
+instruction         show the instruction under the execution finger
+record [LIMIT]      keep an undo journal (of the last LIMIT instructions)
+reverse-step [N]    undo N recorded instructions (default 1)
+reverse-continue    undo back to a breakpoint or the start of the recording
+last-write ARRAY OFFSET
+                    show the recorded instruction that last wrote there
+quit                stop debugging
+An empty line repeats the previous command.";
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Finger(finger) => write!(f, "finger {finger}"),
+            Self::Op(op) => write!(f, "op {op:?}"),
+        }
+    }
+}
+
+impl<C: Console> Debugger<C> {
+    pub fn new(machine: Machine<C>) -> Self {
+        Self {
+            machine,
+            breakpoints: BTreeMap::new(),
+            next_breakpoint: 1,
+        }
+    }
+    pub fn machine(&self) -> &Machine<C> {
+        &self.machine
+    }
+    pub fn machine_mut(&mut self) -> &mut Machine<C> {
+        &mut self.machine
+    }
+    pub fn into_machine(self) -> Machine<C> {
+        self.machine
+    }
+    /// Adds a breakpoint and returns its number.
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        let n = self.next_breakpoint;
+        self.next_breakpoint += 1;
+        self.breakpoints.insert(n, breakpoint);
+        n
+    }
+    pub fn remove_breakpoint(&mut self, n: usize) -> Option<Breakpoint> {
+        self.breakpoints.remove(&n)
+    }
+    pub fn breakpoints(&self) -> impl Iterator<Item = (usize, &Breakpoint)> {
+        self.breakpoints.iter().map(|(&n, b)| (n, b))
+    }
+    /// Executes up to `n` instructions, stopping early at a halt or at a
+    /// breakpoint.
+    pub fn step(&mut self, n: usize) -> Result<Stop, Fault> {
+        for i in 0..n {
+            if i > 0 {
+                if let Some(b) = self.breakpoint() {
+                    return Ok(Stop::Breakpoint(b));
+                }
+            }
+            match self.machine.step()? {
+                StepOutcome::Running => {}
+                StepOutcome::Halted => return Ok(Stop::Halted),
+                StepOutcome::Watchpoint(hit) => return Ok(Stop::Watchpoint(hit)),
+            }
+        }
+        Ok(Stop::Stepped)
+    }
+    /// Runs until a breakpoint is reached or the machine halts. The
+    /// instruction under the finger is always executed, so continuing from
+    /// a breakpoint does not stop at it again right away.
+    pub fn cont(&mut self) -> Result<Stop, Fault> {
+        loop {
+            match self.machine.step()? {
+                StepOutcome::Running => {}
+                StepOutcome::Halted => return Ok(Stop::Halted),
+                StepOutcome::Watchpoint(hit) => return Ok(Stop::Watchpoint(hit)),
+            }
+            if let Some(b) = self.breakpoint() {
+                return Ok(Stop::Breakpoint(b));
+            }
+        }
+    }
+    /// Undoes up to `n` recorded instructions, stopping early at a
+    /// breakpoint or when the journal runs out.
+    pub fn reverse_step(&mut self, n: usize) -> Result<Stop, FaultKind> {
+        for i in 0..n {
+            if i > 0 {
+                if let Some(b) = self.breakpoint() {
+                    return Ok(Stop::Breakpoint(b));
+                }
+            }
+            if !self.machine.unstep()? {
+                return Ok(Stop::Beginning);
+            }
+        }
+        Ok(Stop::Stepped)
+    }
+    /// Undoes recorded instructions until a breakpoint is reached or the
+    /// journal runs out, undoing at least one like `cont` executes one.
+    pub fn reverse_cont(&mut self) -> Result<Stop, FaultKind> {
+        loop {
+            if !self.machine.unstep()? {
+                return Ok(Stop::Beginning);
+            }
+            if let Some(b) = self.breakpoint() {
+                return Ok(Stop::Breakpoint(b));
+            }
+        }
+    }
+    /// Reads commands from `input` until it ends or `quit` is given.
+    pub fn repl(&mut self, mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        let mut previous = String::new();
+        loop {
+            self.machine.console_mut().flush()?;
+            write!(output, "(um) ")?;
+            output.flush()?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                writeln!(output)?;
+                return Ok(());
+            }
+            if line.trim().is_empty() {
+                line = previous.clone();
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            match self.command(&words, &mut output) {
+                Ok(Flow::Quit) => return Ok(()),
+                Ok(Flow::Continue) => {}
+                Err(message) => writeln!(output, "{message}")?,
+            }
+            previous = line;
+        }
+    }
+
+    /* PRIVATE */
+    /// The number of a breakpoint at the instruction under the finger.
+    fn breakpoint(&self) -> Option<usize> {
+        let finger = self.machine.finger();
+        let op = self.machine.peek().ok().map(|i| i.op);
+        self.breakpoints
+            .iter()
+            .find(|(_, &b)| match b {
+                Breakpoint::Finger(f) => f == finger,
+                Breakpoint::Op(o) => Some(o) == op,
+            })
+            .map(|(&n, _)| n)
+    }
+    fn command(&mut self, words: &[&str], out: &mut impl Write) -> Result<Flow, String> {
+        let io = |e: io::Error| e.to_string();
+        match words {
+            ["s" | "step"] => {
+                let stop = self.step(1);
+                self.report(stop, out).map_err(io)?
+            }
+            ["s" | "step", n] => {
+                let stop = self.step(number(n)? as usize);
+                self.report(stop, out).map_err(io)?
+            }
+            ["c" | "continue"] => {
+                let stop = self.cont();
+                self.report(stop, out).map_err(io)?
+            }
+            ["b" | "break", "op", name] => {
+                let b = Breakpoint::Op(name.parse()?);
+                let n = self.add_breakpoint(b);
+                writeln!(out, "Breakpoint {n} at {b}").map_err(io)?;
+            }
+            ["b" | "break", finger] => {
+                let b = Breakpoint::Finger(number(finger)? as usize);
+                let n = self.add_breakpoint(b);
+                writeln!(out, "Breakpoint {n} at {b}").map_err(io)?;
+            }
+            ["d" | "delete", n] => {
+                let n = number(n)? as usize;
+                self.remove_breakpoint(n).ok_or(format!("No breakpoint number {n}"))?;
+            }
+            ["breakpoints"] => {
+                for (n, b) in self.breakpoints() {
+                    writeln!(out, "{n}\t{b}").map_err(io)?;
+                }
+            }
+            ["w" | "watch", rest @ ..] => {
+                let w = watchpoint(rest)?;
+                let n = self.machine.add_watchpoint(w);
+                writeln!(out, "Watchpoint {n} on {w}").map_err(io)?;
+            }
+            ["unwatch", n] => {
+                let n = number(n)? as usize;
+                self.machine.remove_watchpoint(n).ok_or(format!("No watchpoint number {n}"))?;
+            }
+            ["watchpoints"] => {
+                for (n, w) in self.machine.watchpoints() {
+                    writeln!(out, "{n}\t{w}").map_err(io)?;
+                }
+            }
+            ["r" | "registers"] => self.registers(out).map_err(io)?,
+            ["x" | "dump", array, rest @ ..] if rest.len() <= 2 => {
+                let array: MemoryAddress = number(array)?.into();
+                let start = rest.first().map(|s| number(s)).transpose()?.unwrap_or(0);
+                let count = rest.get(1).map(|s| number(s)).transpose()?.unwrap_or(16);
+                self.dump(array, start as usize, count as usize, out)?;
+            }
+            ["i" | "instruction"] => self.instruction(out).map_err(io)?,
+            ["record"] => self.machine.set_journal(Journal::new()),
+            ["record", limit] => self.machine.set_journal(Journal::with_limit(number(limit)? as usize)),
+            ["rs" | "reverse-step", rest @ ..] if rest.len() <= 1 => {
+                let n = rest.first().map(|s| number(s)).transpose()?.unwrap_or(1);
+                self.recording()?;
+                let stop = self.reverse_step(n as usize).map_err(|kind| format!("Could not undo: {kind}"))?;
+                self.report(Ok(stop), out).map_err(io)?
+            }
+            ["rc" | "reverse-continue"] => {
+                self.recording()?;
+                let stop = self.reverse_cont().map_err(|kind| format!("Could not undo: {kind}"))?;
+                self.report(Ok(stop), out).map_err(io)?
+            }
+            ["last-write", array, offset] => {
+                let array: MemoryAddress = number(array)?.into();
+                let offset = number(offset)?;
+                let journal = self.recording()?;
+                match journal.last_write(array, offset) {
+                    Some(e) => writeln!(out, "Step {}, finger {}: {:?}", e.step, e.finger, e.op),
+                    None => writeln!(out, "Not written since the start of the recording"),
+                }
+                .map_err(io)?
+            }
+            ["h" | "help"] => writeln!(out, "{HELP}").map_err(io)?,
+            ["q" | "quit"] => return Ok(Flow::Quit),
+            _ => return Err(format!("Unknown command `{}`, try `help`", words.join(" "))),
+        }
+        Ok(Flow::Continue)
+    }
+    fn recording(&self) -> Result<&Journal, String> {
+        self.machine.journal().ok_or("Not recording, try `record`".to_string())
+    }
+    fn report(&mut self, stop: Result<Stop, Fault>, out: &mut impl Write) -> io::Result<()> {
+        self.machine.console_mut().flush()?;
+        match stop {
+            Ok(Stop::Stepped) => {}
+            Ok(Stop::Breakpoint(n)) => writeln!(out, "Breakpoint {n}")?,
+            Ok(Stop::Halted) => writeln!(out, "The machine halted")?,
+            Ok(Stop::Watchpoint(hit)) => {
+                write!(out, "Watchpoint {} at finger {}: ", hit.watchpoint, hit.finger)?;
+                match hit.old {
+                    Some(old) => writeln!(out, "{old:#x} -> {:#x}", hit.new)?,
+                    None => writeln!(out, "{:#x}", hit.new)?,
+                }
+            }
+            Ok(Stop::Beginning) => writeln!(out, "Reached the start of the recording")?,
+            Err(fault) => writeln!(out, "The machine failed: {fault}")?,
+        }
+        self.instruction(out)
+    }
+    fn instruction(&self, out: &mut impl Write) -> io::Result<()> {
+        let finger = self.machine.finger();
+        match self.machine.peek() {
+            Ok(i) => writeln!(out, "{finger}:\t{i}"),
+            Err(fault) => writeln!(out, "{finger}:\t{}", fault.kind),
+        }
+    }
+    fn registers(&self, out: &mut impl Write) -> io::Result<()> {
+        let r = self.machine.registers();
+        for i in 0..8u32 {
+            let value: u32 = r[i.into()].into();
+            writeln!(out, "r{i}\t{value:#010x}\t{value}")?;
+        }
+        writeln!(out, "finger\t{}", self.machine.finger())
+    }
+    fn dump(&self, array: MemoryAddress, start: usize, count: usize, out: &mut impl Write) -> Result<(), String> {
+        let platters = self.machine.memory().array(array).map_err(|kind| kind.to_string())?.as_slice();
+        let end = start.saturating_add(count).min(platters.len());
+        for row in (start..end).step_by(4) {
+            let line: Vec<String> = platters[row..end.min(row + 4)]
+                .iter()
+                .map(|p| format!("{p:#010x}"))
+                .collect();
+            writeln!(out, "{row}:\t{}", line.join(" ")).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a decimal or `0x`-prefixed hexadecimal number.
+fn number(s: &str) -> Result<u32, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| format!("`{s}` is not a number"))
+}
+
+/// Parses the arguments of `watch`.
+fn watchpoint(words: &[&str]) -> Result<Watchpoint, String> {
+    let (what, condition) = match words {
+        [what @ .., "if", comparison, value] => (what, Some(Condition { comparison: comparison.parse()?, value: number(value)? })),
+        what => (what, None),
+    };
+    let target = match what {
+        [register] if register.starts_with('r') => match number(&register[1..])? {
+            k if k < 8 => Target::Register(k.into()),
+            _ => return Err(format!("No register {register}")),
+        },
+        ["read", array, offset] => Target::Read { array: number(array)?.into(), offset: number(offset)? },
+        ["write", array, offset] => Target::Write { array: number(array)?.into(), offset: number(offset)? },
+        ["alloc", array] => Target::Alloc(number(array)?.into()),
+        ["aband", array] => Target::Aband(number(array)?.into()),
+        _ => return Err(format!("Cannot watch `{}`, try `help`", what.join(" "))),
+    };
+    Ok(Watchpoint { target, condition })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{console::Buffer, instruction::{Instruction, RawInstruction}, memory::{ArrayOfPlatters, Platter}};
+
+    fn debugger(ops: &[(Op, u32)]) -> Debugger<Buffer> {
+        let code: Vec<Platter> = ops
+            .iter()
+            .map(|&(op, sa)| {
+                let raw: RawInstruction = Instruction {
+                    op,
+                    a: 1.into(),
+                    b: 2.into(),
+                    c: 3.into(),
+                    sa: sa.into(),
+                    value: 1.into(),
+                }.into();
+                raw.into()
+            })
+            .collect();
+        let code: ArrayOfPlatters = code.into();
+        let mut m = Machine::with_console(Buffer::default());
+        m.load(code.into());
+        Debugger::new(m)
+    }
+
+    fn session(d: &mut Debugger<Buffer>, commands: &str) -> String {
+        let mut out = Vec::new();
+        d.repl(commands.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut d = debugger(&[(Op::Orth, 3), (Op::Orth, 2), (Op::Add, 0), (Op::Orth, 4), (Op::Halt, 0)]);
+        let b = d.add_breakpoint(Breakpoint::Op(Op::Add));
+        d.add_breakpoint(Breakpoint::Finger(1));
+        assert_eq!(d.cont(), Ok(Stop::Breakpoint(2)));
+        assert_eq!(d.cont(), Ok(Stop::Breakpoint(b)));
+        assert_eq!(d.machine().finger(), 2);
+        d.remove_breakpoint(b);
+        assert_eq!(d.step(10), Ok(Stop::Halted));
+        assert_eq!(d.machine().finger(), 4);
+    }
+
+    #[test]
+    fn repl() {
+        let mut d = debugger(&[(Op::Orth, 3), (Op::Orth, 2), (Op::Add, 0), (Op::Halt, 0)]);
+        let out = session(&mut d, "break op add\ncontinue\n\nregisters\ndump 0 1 2\nbogus\nquit\nstep\n");
+        assert!(out.contains("Breakpoint 1 at op Add\n"));
+        assert!(out.contains("Breakpoint 1\n2:\t"));
+        assert!(out.contains("The machine halted\n3:\t"));
+        assert!(out.contains("r1\t0x00000002\t2\n"));
+        assert!(out.contains("1:\t0xd4000001 0x30000053\n"));
+        assert!(out.contains("Unknown command `bogus`"));
+        assert_eq!(d.machine().finger(), 3);
+    }
+
+    #[test]
+    fn watchpoints() {
+        // r3 = 1, alloc r2 <- size r3, r2 = 1, amend [r1][r2] = r3.
+        let mut d = debugger(&[(Op::Orth, 3), (Op::Alloc, 0), (Op::Orth, 2), (Op::Amend, 0), (Op::Halt, 0)]);
+        let out = session(&mut d, "watch r3 if > 1\nwatch r2\nwatch write 0 1\nwatch bogus\nwatchpoints\nc\nunwatch 2\nc\nc\nquit\n");
+        assert!(out.contains("Watchpoint 1 on r3 if > 0x1\n"));
+        assert!(out.contains("Cannot watch `bogus`"));
+        assert!(out.contains("3\twrite 0 1\n"));
+        assert!(out.contains("Watchpoint 2 at finger 1: 0x0 -> 0x1\n2:\t"));
+        assert!(out.contains("Watchpoint 3 at finger 3: 0x1\n4:\t"));
+        assert!(out.contains("The machine halted\n"));
+    }
+
+    #[test]
+    fn reverse() {
+        let mut d = debugger(&[(Op::Orth, 3), (Op::Orth, 2), (Op::Add, 0), (Op::Orth, 4), (Op::Halt, 0)]);
+        let out = session(&mut d, "reverse-step\nrecord\nstep 4\nreverse-step\nregisters\nbreak 1\nrc\nrc\n");
+        assert!(out.contains("Not recording, try `record`"));
+        assert!(out.contains("r4\t0x00000000\t0\n"));
+        assert!(out.contains("Breakpoint 1\n1:\t"));
+        assert!(out.contains("Reached the start of the recording\n0:\t"));
+        assert_eq!(d.machine().registers(), &Default::default());
+        d.remove_breakpoint(1);
+        assert_eq!(d.step(10), Ok(Stop::Halted));
+        assert_eq!(d.machine().journal().map(|j| j.steps()), Some(4));
+    }
+}
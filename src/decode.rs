@@ -0,0 +1,100 @@
+use crate::{
+    instruction::Instruction,
+    memory::{ArrayOfPlatters, Platter},
+    op::Op,
+    register,
+};
+
+/// An instruction decoded once, ahead of execution. For `Op::Orth`, `a`
+/// holds the special register and `value` the immediate.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Decoded {
+    pub op: Op,
+    pub a: register::Index,
+    pub b: register::Index,
+    pub c: register::Index,
+    pub value: Platter,
+}
+
+impl From<Instruction> for Decoded {
+    fn from(i: Instruction) -> Self {
+        let a = match i.op {
+            Op::Orth => i.sa,
+            _ => i.a,
+        };
+        Self {
+            op: i.op,
+            a,
+            b: i.b,
+            c: i.c,
+            value: i.value.into(),
+        }
+    }
+}
+
+impl Decoded {
+    /// Decodes `platter`, or `None` if it is not a valid instruction.
+    pub fn new(platter: Platter) -> Option<Self> {
+        Instruction::try_from(platter).ok().map(Into::into)
+    }
+}
+
+/// The decoded platters of the '0' array. It has to be rebuilt whenever
+/// another array is loaded, and updated whenever the '0' array is amended.
+#[derive(Debug, Default, Clone)]
+pub struct DecodeCache(Vec<Option<Decoded>>);
+
+impl DecodeCache {
+    pub fn new(program: &ArrayOfPlatters) -> Self {
+        Self(program.as_slice().iter().map(|&p| Decoded::new(p)).collect())
+    }
+    /// The instruction at `finger`, or `None` if the finger is outside the
+    /// program or the platter there is not a valid instruction.
+    pub fn get(&self, finger: usize) -> Option<Decoded> {
+        self.0.get(finger).copied().flatten()
+    }
+    /// Every platter of the '0' array, decoded.
+    pub fn as_slice(&self) -> &[Option<Decoded>] {
+        &self.0
+    }
+    pub fn update(&mut self, offset: usize, platter: Platter) {
+        if let Some(d) = self.0.get_mut(offset) {
+            *d = Decoded::new(platter);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::RawInstruction;
+
+    #[test]
+    fn orth_uses_special_register() {
+        let raw: RawInstruction = Instruction {
+            op: Op::Orth,
+            a: 0.into(),
+            b: 0.into(),
+            c: 0.into(),
+            sa: 5.into(),
+            value: 0x1becafe.into(),
+        }.into();
+        let d = Decoded::new(raw.into()).unwrap();
+        assert_eq!(d.op, Op::Orth);
+        assert_eq!(d.a, 5.into());
+        assert_eq!(d.value, 0x1becafe);
+    }
+
+    #[test]
+    fn update_invalidates() {
+        let program: ArrayOfPlatters = vec![0x3000_0000u32, 0xf000_0000].into();
+        let mut cache = DecodeCache::new(&program);
+        assert_eq!(cache.get(0).unwrap().op, Op::Add);
+        assert_eq!(cache.get(1), None);
+        assert_eq!(cache.get(2), None);
+        cache.update(1, 0x7000_0000);
+        assert_eq!(cache.get(1).unwrap().op, Op::Halt);
+        cache.update(0, 0xe000_0000);
+        assert_eq!(cache.get(0), None);
+    }
+}
@@ -0,0 +1,142 @@
+use std::{io::{self, Write}, ops::Range};
+use crate::{instruction::{Instruction, RawInstruction}, memory::Platter, program::Program};
+
+/// What to disassemble and how.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The offsets of the platters to disassemble.
+    pub range: Range<usize>,
+    /// Show platters that look more like data than code as `.data`, rather
+    /// than only those that are not valid instructions.
+    pub guess_data: bool,
+    /// Write source for the assembler, with the offset and the raw platter
+    /// in a comment. Platters that would not assemble back to the same bits
+    /// are written as `.data`.
+    pub source: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            range: 0..usize::MAX,
+            guess_data: false,
+            source: false,
+        }
+    }
+}
+
+/// A disassembled platter.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Line {
+    Instruction(Instruction),
+    Data(Platter),
+}
+
+/// Runs of at least this many byte-sized platters are taken to be strings.
+const MIN_STRING: usize = 4;
+/// The bits between the operator and the registers, which only `Op::Orth`
+/// uses. Compiled code leaves them clear.
+const UNUSED_BITS: Platter = 0x0fff_fe00;
+
+/// Classifies every platter of `program`.
+pub fn lines(program: &Program, guess_data: bool) -> Vec<Line> {
+    let platters = program.as_slice();
+    let mut data = vec![false; platters.len()];
+    if guess_data {
+        let mut start = 0;
+        for (offset, &p) in platters.iter().enumerate() {
+            if p > 0xff {
+                start = offset + 1;
+            } else if offset + 1 - start >= MIN_STRING {
+                data[start..=offset].fill(true);
+            }
+            data[offset] |= p == 0 || (p >> 28 != 13 && p & UNUSED_BITS != 0);
+        }
+    }
+    platters
+        .iter()
+        .zip(data)
+        .map(|(&p, data)| match Instruction::try_from(p) {
+            Ok(i) if !data => Line::Instruction(i),
+            _ => Line::Data(p),
+        })
+        .collect()
+}
+
+impl std::fmt::Display for Line {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Instruction(i) => write!(f, "{i}"),
+            Self::Data(p) => write!(f, ".data {p:#010x}"),
+        }
+    }
+}
+
+/// Writes one line per platter: its offset, the raw platter and its
+/// assembly form.
+pub fn disassemble(program: &Program, options: &Options, out: &mut impl Write) -> io::Result<()> {
+    let platters = program.as_slice();
+    let lines = lines(program, options.guess_data);
+    let end = options.range.end.min(platters.len());
+    for offset in options.range.start.min(end)..end {
+        let p = platters[offset];
+        match &lines[offset] {
+            line if !options.source => writeln!(out, "{offset:08x}: {p:08x}  {line}")?,
+            Line::Instruction(i) if !is_canonical(i, p) => {
+                let data = Line::Data(p).to_string();
+                writeln!(out, "        {data:<32}; {offset:08x}: {p:08x}  {i}")?
+            }
+            line => writeln!(out, "        {:<32}; {offset:08x}: {p:08x}", line.to_string())?,
+        }
+    }
+    Ok(())
+}
+
+/// Whether assembling `i` gives back `platter`.
+fn is_canonical(i: &Instruction, platter: Platter) -> bool {
+    let raw: RawInstruction = i.canonical().into();
+    Into::<Platter>::into(raw) == platter
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::ArrayOfPlatters;
+
+    fn program(platters: Vec<Platter>) -> Program {
+        let array: ArrayOfPlatters = platters.into();
+        array.into()
+    }
+
+    #[test]
+    fn listing() {
+        let p = program(vec![0xd7be_cafe, 0x3000_0053, 0xf000_0000, 0x7000_0000]);
+        let mut out = Vec::new();
+        let options = Options { range: 1..3, ..Default::default() };
+        disassemble(&p, &options, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "00000001: 30000053  add r1, r2, r3\n00000002: f0000000  .data 0xf0000000\n",
+        );
+    }
+
+    #[test]
+    fn guess_data() {
+        let p = program(vec![0x7000_0000, 0x48, 0x69, 0x21, 0x0a, 0x41, 0x0, 0x42, 0xd7be_cafe, 0x91b5_3a9f]);
+        let data: Vec<bool> = lines(&p, true).iter().map(|l| matches!(l, Line::Data(_))).collect();
+        assert_eq!(data, [false, true, true, true, true, true, true, true, false, true]);
+        let data: Vec<bool> = lines(&p, false).iter().map(|l| matches!(l, Line::Data(_))).collect();
+        assert_eq!(data, [false; 10]);
+    }
+
+    #[test]
+    fn source_round_trip() {
+        let p = program(vec![0xd7be_cafe, 0x3000_0053, 0xf000_0000, 0x8000_00d1, 0x3000_2053, 0x7000_0000]);
+        let mut out = Vec::new();
+        disassemble(&p, &Options { source: true, ..Default::default() }, &mut out).unwrap();
+        let source = String::from_utf8(out).unwrap();
+        assert!(source.contains("alloc r2, r1\n"));
+        let assembled = crate::asm::assemble(&source).unwrap();
+        assert_eq!(assembled.as_slice(), p.as_slice());
+    }
+}
@@ -0,0 +1,86 @@
+use std::fmt;
+use crate::{instruction::Instruction, memory::{MemoryAddress, Platter}, op::InvalidOpcode};
+
+/// The circumstances under which the spec allows the machine to Fail.
+#[derive(Debug, PartialEq, Clone)]
+pub enum FaultKind {
+    /// The execution finger indicates a platter that is not a valid instruction.
+    InvalidOpcode(u8),
+    /// The execution finger aims outside the capacity of the 0 array.
+    FingerOutOfRange,
+    /// An array that has not been allocated, or has been abandoned, was
+    /// indexed, amended or abandoned.
+    InactiveArray(MemoryAddress),
+    /// An offset lies outside the capacity of an active array.
+    OutOfBounds { array: MemoryAddress, offset: Platter },
+    /// The program tried to abandon the '0' array.
+    AbandonProgram,
+    DivideByZero,
+    /// The program tried to output a value larger than 255.
+    OutputOutOfRange(Platter),
+    /// The program tried to load a program from an array that is not active.
+    LoadInactive(MemoryAddress),
+    /// Allocating an array of this many platters would go over the memory
+    /// quota, or the host has no memory left for it.
+    OutOfMemory { size: usize },
+    /// The console could not be read from or written to.
+    Console(std::io::ErrorKind),
+    /// The trace could not be written.
+    Trace(std::io::ErrorKind),
+}
+
+/// A failure together with where in the program it happened.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Fault {
+    pub kind: FaultKind,
+    /// The offset into array 0 of the faulting instruction.
+    pub finger: usize,
+    /// The faulting instruction, unless it could not be decoded.
+    pub instruction: Option<Instruction>,
+}
+
+impl From<InvalidOpcode> for FaultKind {
+    fn from(InvalidOpcode(op): InvalidOpcode) -> Self {
+        Self::InvalidOpcode(op)
+    }
+}
+
+impl From<std::io::Error> for FaultKind {
+    fn from(e: std::io::Error) -> Self {
+        Self::Console(e.kind())
+    }
+}
+
+impl fmt::Display for FaultKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidOpcode(op) => write!(f, "invalid opcode {op}"),
+            Self::FingerOutOfRange => write!(f, "execution finger outside array 0"),
+            Self::InactiveArray(addr) => write!(f, "array {addr} is not active"),
+            Self::OutOfBounds { array, offset } => {
+                write!(f, "offset {offset} is outside array {array}")
+            }
+            Self::AbandonProgram => write!(f, "cannot abandon array 0"),
+            Self::DivideByZero => write!(f, "division by zero"),
+            Self::OutputOutOfRange(value) => {
+                write!(f, "cannot output {value}, only values up to 255 are allowed")
+            }
+            Self::LoadInactive(addr) => write!(f, "cannot load program from inactive array {addr}"),
+            Self::OutOfMemory { size } => write!(f, "out of memory for an array of {size} platters"),
+            Self::Console(kind) => write!(f, "console error: {kind}"),
+            Self::Trace(kind) => write!(f, "trace error: {kind}"),
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at finger {}", self.kind, self.finger)?;
+        if let Some(i) = &self.instruction {
+            write!(f, " ({i:?})")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Fault {}
@@ -0,0 +1,524 @@
+//! A stub for the GDB remote serial protocol, so that `gdb` can debug a
+//! machine over a socket, e.g. with `target remote localhost:1234` after
+//! `um --gdb localhost:1234 FILE`.
+//!
+//! The target description has nine 32-bit registers: r0 to r7, then `pc`.
+//! Array 0 is the memory, four bytes to a platter in little-endian order,
+//! so the platter at offset N is at address 4N and `pc` is four times the
+//! execution finger. Breakpoints are set on those addresses with `Z0`, and
+//! `s` and `c` step and continue the machine. A program that halts exits
+//! with status 0, and a fault stops it with a signal, leaving the finger on
+//! the faulting instruction.
+
+use std::{
+    collections::BTreeSet,
+    io::{self, Read, Write},
+    net::TcpStream,
+    os::unix::net::UnixStream,
+};
+use crate::{
+    console::Console,
+    fault::{Fault, FaultKind},
+    machine::{Machine, StepOutcome},
+    memory::Platter,
+    register::NUMBER_OF_REGISTERS,
+};
+
+/// How many instructions `c` executes between looks for an interrupt.
+const POLL_INTERVAL: u64 = 1 << 16;
+/// The largest packet the stub accepts, as told to `gdb`.
+const PACKET_SIZE: usize = 0x4000;
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.um.core">
+    <reg name="r0" bitsize="32" type="uint32" regnum="0"/>
+    <reg name="r1" bitsize="32" type="uint32"/>
+    <reg name="r2" bitsize="32" type="uint32"/>
+    <reg name="r3" bitsize="32" type="uint32"/>
+    <reg name="r4" bitsize="32" type="uint32"/>
+    <reg name="r5" bitsize="32" type="uint32"/>
+    <reg name="r6" bitsize="32" type="uint32"/>
+    <reg name="r7" bitsize="32" type="uint32"/>
+    <reg name="pc" bitsize="32" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+/// A socket `gdb` is connected through.
+pub trait Connection: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+impl Connection for UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+/// How a `gdb` session ended.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Ending {
+    /// `gdb` detached, leaving the machine to run on.
+    Detached,
+    /// `gdb` killed the machine, or hung up.
+    Killed,
+}
+
+/// What the stub received.
+#[derive(Debug, PartialEq)]
+enum Received {
+    Packet(Vec<u8>),
+    /// `gdb` asked a running machine to stop.
+    Interrupt,
+}
+
+/// Why the machine stopped, as a stop reply packet.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Stopped {
+    Signal(u8),
+    Breakpoint,
+    Exited,
+}
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGFPE: u8 = 8;
+const SIGSEGV: u8 = 11;
+
+/// Serves one `gdb` connection for a machine.
+pub struct Stub<C, S> {
+    machine: Machine<C>,
+    stream: S,
+    /// Bytes received but not looked at yet.
+    pending: Vec<u8>,
+    /// Fingers to stop at.
+    breakpoints: BTreeSet<usize>,
+    stopped: Stopped,
+}
+
+impl<C: Console, S: Connection> Stub<C, S> {
+    pub fn new(machine: Machine<C>, stream: S) -> Self {
+        Self {
+            machine,
+            stream,
+            pending: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            stopped: Stopped::Signal(SIGTRAP),
+        }
+    }
+    pub fn machine(&self) -> &Machine<C> {
+        &self.machine
+    }
+    pub fn into_machine(self) -> Machine<C> {
+        self.machine
+    }
+    /// Answers packets until `gdb` detaches, kills the machine or hangs up.
+    pub fn serve(&mut self) -> io::Result<Ending> {
+        loop {
+            let packet = match self.receive()? {
+                None => return Ok(Ending::Killed),
+                Some(Received::Interrupt) => continue,
+                Some(Received::Packet(packet)) => packet,
+            };
+            let packet = String::from_utf8_lossy(&packet).into_owned();
+            match packet.as_str() {
+                "D" | "D;1" => return self.send("OK").map(|()| Ending::Detached),
+                "k" | "vKill;1" => return Ok(Ending::Killed),
+                _ => {}
+            }
+            let reply = self.answer(&packet)?;
+            self.send(&reply)?;
+        }
+    }
+
+    /* PRIVATE */
+    /// The reply to `packet`, empty for packets the stub does not know.
+    fn answer(&mut self, packet: &str) -> io::Result<String> {
+        let error = |_| "E01".to_string();
+        let reply = match packet.split_at(packet.chars().next().map_or(0, char::len_utf8)) {
+            _ if packet.starts_with("qSupported") => {
+                format!("PacketSize={PACKET_SIZE:x};qXfer:features:read+;swbreak+")
+            }
+            _ if packet.starts_with("qXfer:features:read:target.xml:") => {
+                let range = &packet["qXfer:features:read:target.xml:".len()..];
+                transfer(TARGET_XML, range).unwrap_or_else(error)
+            }
+            ("?", "") => stop_reply(self.stopped),
+            ("g", "") => self.registers().iter().map(|&v| word(v)).collect(),
+            ("G", values) => self.set_registers(values).map(|()| "OK".to_string()).unwrap_or_else(error),
+            ("p", n) => hex(n)
+                .and_then(|n| self.registers().get(n as usize).copied().ok_or(()))
+                .map(word)
+                .unwrap_or_else(error),
+            ("P", assignment) => self.set_register(assignment).map(|()| "OK".to_string()).unwrap_or_else(error),
+            ("m", range) => self.read_memory(range).unwrap_or_else(error),
+            ("M", write) => self.write_memory(write).map(|()| "OK".to_string()).unwrap_or_else(error),
+            ("Z" | "z", breakpoint) => match breakpoint.strip_prefix("0,") {
+                Some(breakpoint) => self.breakpoint(packet.starts_with('Z'), breakpoint).unwrap_or_else(error),
+                None => String::new(),
+            },
+            ("s" | "c", address) => {
+                if !address.is_empty() {
+                    let Ok(address) = hex(address) else { return Ok("E01".to_string()) };
+                    self.machine.set_finger(address as usize / 4);
+                }
+                self.stopped = match packet.starts_with('s') {
+                    true => self.step(),
+                    false => self.cont()?,
+                };
+                stop_reply(self.stopped)
+            }
+            ("H", _) => "OK".to_string(),
+            _ => match packet {
+                "qAttached" => "1".to_string(),
+                "qC" => "QC1".to_string(),
+                "qfThreadInfo" => "m1".to_string(),
+                "qsThreadInfo" => "l".to_string(),
+                "qSymbol::" => "OK".to_string(),
+                _ => String::new(),
+            },
+        };
+        Ok(reply)
+    }
+    /// r0 to r7, then `pc`.
+    fn registers(&self) -> [Platter; NUMBER_OF_REGISTERS + 1] {
+        let r = self.machine.registers();
+        let mut values = [0; NUMBER_OF_REGISTERS + 1];
+        for (i, value) in values.iter_mut().take(NUMBER_OF_REGISTERS).enumerate() {
+            *value = r[(i as u32).into()].into();
+        }
+        values[NUMBER_OF_REGISTERS] = (self.machine.finger() as Platter).wrapping_mul(4);
+        values
+    }
+    fn set(&mut self, n: usize, value: Platter) -> Result<(), ()> {
+        match n {
+            NUMBER_OF_REGISTERS => self.machine.set_finger(value as usize / 4),
+            n if n < NUMBER_OF_REGISTERS => self.machine.registers_mut()[(n as u32).into()] = value.into(),
+            _ => return Err(()),
+        }
+        Ok(())
+    }
+    fn set_registers(&mut self, values: &str) -> Result<(), ()> {
+        let words = words(values)?;
+        if words.len() != NUMBER_OF_REGISTERS + 1 {
+            return Err(());
+        }
+        words.into_iter().enumerate().try_for_each(|(n, value)| self.set(n, value))
+    }
+    fn set_register(&mut self, assignment: &str) -> Result<(), ()> {
+        let (n, value) = assignment.split_once('=').ok_or(())?;
+        match words(value)?.as_slice() {
+            &[value] => self.set(hex(n)? as usize, value),
+            _ => Err(()),
+        }
+    }
+    /// The bytes of array 0 from `addr,length`, in hex.
+    fn read_memory(&self, range: &str) -> Result<String, ()> {
+        let (start, length) = range.split_once(',').ok_or(())?;
+        let (start, length) = (hex(start)? as usize, hex(length)? as usize);
+        let program = self.machine.memory().array(0.into()).map_err(|_| ())?.as_slice();
+        let bytes: String = (start..start.saturating_add(length))
+            .map_while(|address| program.get(address / 4).map(|p| p.to_le_bytes()[address % 4]))
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        match bytes.is_empty() && length > 0 {
+            true => Err(()),
+            false => Ok(bytes),
+        }
+    }
+    /// Writes `addr,length:bytes` into array 0.
+    fn write_memory(&mut self, write: &str) -> Result<(), ()> {
+        let (range, data) = write.split_once(':').ok_or(())?;
+        let (start, _) = range.split_once(',').ok_or(())?;
+        let start = hex(start)? as usize;
+        for (k, byte) in bytes(data)?.into_iter().enumerate() {
+            let address = start + k;
+            let offset = Platter::try_from(address / 4).map_err(|_| ())?;
+            let platter = self.machine.memory().get(0.into(), offset).map_err(|_| ())?;
+            let mut le = platter.to_le_bytes();
+            le[address % 4] = byte;
+            self.machine.amend(0.into(), offset, Platter::from_le_bytes(le)).map_err(|_| ())?;
+        }
+        Ok(())
+    }
+    /// Inserts or removes the breakpoint at `addr,kind`.
+    fn breakpoint(&mut self, insert: bool, breakpoint: &str) -> Result<String, ()> {
+        let (address, _) = breakpoint.split_once(',').ok_or(())?;
+        let finger = hex(address)? as usize / 4;
+        match insert {
+            true => self.breakpoints.insert(finger),
+            false => self.breakpoints.remove(&finger),
+        };
+        Ok("OK".to_string())
+    }
+    fn step(&mut self) -> Stopped {
+        match self.machine.step() {
+            Ok(StepOutcome::Halted) => Stopped::Exited,
+            Ok(_) => Stopped::Signal(SIGTRAP),
+            Err(fault) => Stopped::Signal(signal(&fault)),
+        }
+    }
+    /// Runs until a breakpoint, a halt, a fault or an interrupt. The
+    /// instruction under the finger is always executed.
+    fn cont(&mut self) -> io::Result<Stopped> {
+        let mut steps = 0u64;
+        loop {
+            steps += 1;
+            match self.step() {
+                Stopped::Signal(SIGTRAP) => {}
+                stopped => return Ok(stopped),
+            }
+            if self.breakpoints.contains(&self.machine.finger()) {
+                return Ok(Stopped::Breakpoint);
+            }
+            if steps.is_multiple_of(POLL_INTERVAL) && self.interrupted()? {
+                return Ok(Stopped::Signal(SIGINT));
+            }
+        }
+    }
+    /// Whether `gdb` sent an interrupt, without waiting for it to.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut buffer = [0; 1024];
+        let read = self.stream.read(&mut buffer);
+        self.stream.set_nonblocking(false)?;
+        match read {
+            Ok(n) => self.pending.extend_from_slice(&buffer[..n]),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+        match self.pending.iter().position(|&b| b == 0x03) {
+            Some(i) => {
+                self.pending.remove(i);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+    fn byte(&mut self) -> io::Result<Option<u8>> {
+        if self.pending.is_empty() {
+            let mut buffer = [0; 1024];
+            let n = self.stream.read(&mut buffer)?;
+            self.pending.extend_from_slice(&buffer[..n]);
+        }
+        match self.pending.is_empty() {
+            true => Ok(None),
+            false => Ok(Some(self.pending.remove(0))),
+        }
+    }
+    /// The next packet or interrupt, acknowledging packets as they come,
+    /// or `None` once `gdb` hangs up.
+    fn receive(&mut self) -> io::Result<Option<Received>> {
+        loop {
+            match self.byte()? {
+                None => return Ok(None),
+                Some(0x03) => return Ok(Some(Received::Interrupt)),
+                Some(b'$') => {}
+                // Acknowledgements of what the stub sent, and noise.
+                Some(_) => continue,
+            }
+            let mut packet = Vec::new();
+            let mut escaped = false;
+            let mut sum = 0u8;
+            loop {
+                let byte = self.byte()?;
+                match (byte, escaped) {
+                    (None, _) => return Ok(None),
+                    (Some(b'#'), false) => break,
+                    _ => {}
+                }
+                sum = sum.wrapping_add(byte.unwrap_or(0));
+                match (byte, escaped) {
+                    (Some(b'}'), false) => escaped = true,
+                    (Some(b), true) => {
+                        packet.push(b ^ 0x20);
+                        escaped = false;
+                    }
+                    (Some(b), false) => packet.push(b),
+                    (None, _) => unreachable!(),
+                }
+            }
+            let mut checksum = [0; 2];
+            for digit in &mut checksum {
+                *digit = self.byte()?.unwrap_or(0);
+            }
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|c| u8::from_str_radix(c, 16).ok());
+            if expected == Some(sum) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(Received::Packet(packet)));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+    fn send(&mut self, reply: &str) -> io::Result<()> {
+        let mut data = Vec::with_capacity(reply.len() + 4);
+        for &b in reply.as_bytes() {
+            match b {
+                b'#' | b'$' | b'}' | b'*' => data.extend([b'}', b ^ 0x20]),
+                b => data.push(b),
+            }
+        }
+        let checksum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        self.stream.write_all(b"$")?;
+        self.stream.write_all(&data)?;
+        write!(self.stream, "#{checksum:02x}")?;
+        self.stream.flush()
+    }
+}
+
+fn stop_reply(stopped: Stopped) -> String {
+    match stopped {
+        Stopped::Signal(signal) => format!("S{signal:02x}"),
+        Stopped::Breakpoint => format!("T{SIGTRAP:02x}swbreak:;"),
+        Stopped::Exited => "W00".to_string(),
+    }
+}
+
+/// The signal a fault stops the machine with.
+fn signal(fault: &Fault) -> u8 {
+    match fault.kind {
+        FaultKind::InvalidOpcode(_) => SIGILL,
+        FaultKind::DivideByZero => SIGFPE,
+        _ => SIGSEGV,
+    }
+}
+
+/// The part `offset,length` of an object for `qXfer`.
+fn transfer(object: &str, range: &str) -> Result<String, ()> {
+    let (offset, length) = range.split_once(',').ok_or(())?;
+    let (offset, length) = (hex(offset)? as usize, hex(length)? as usize);
+    let rest = object.get(offset.min(object.len())..).ok_or(())?;
+    match rest.len() <= length {
+        true => Ok(format!("l{rest}")),
+        false => Ok(format!("m{}", rest.get(..length).ok_or(())?)),
+    }
+}
+
+fn hex(s: &str) -> Result<u64, ()> {
+    u64::from_str_radix(s, 16).map_err(|_| ())
+}
+
+fn bytes(s: &str) -> Result<Vec<u8>, ()> {
+    if !s.len().is_multiple_of(2) {
+        return Err(());
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()).ok_or(()))
+        .collect()
+}
+
+/// Little-endian 32-bit words from hex.
+fn words(s: &str) -> Result<Vec<Platter>, ()> {
+    let bytes = bytes(s)?;
+    if !bytes.len().is_multiple_of(4) {
+        return Err(());
+    }
+    Ok(bytes.chunks(4).map(|w| Platter::from_le_bytes([w[0], w[1], w[2], w[3]])).collect())
+}
+
+/// A little-endian 32-bit word in hex.
+fn word(value: Platter) -> String {
+    value.to_le_bytes().iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm::assemble, console::Buffer};
+
+    /// Sends `packet` the way `gdb` does and returns the reply.
+    fn exchange(gdb: &mut UnixStream, packet: &str) -> String {
+        let checksum = packet.bytes().fold(0u8, u8::wrapping_add);
+        write!(gdb, "${packet}#{checksum:02x}").unwrap();
+        let mut reply = Vec::new();
+        let mut byte = [0];
+        loop {
+            gdb.read_exact(&mut byte).unwrap();
+            match byte[0] {
+                b'+' if reply.is_empty() => continue,
+                b'#' => break,
+                b => reply.push(b),
+            }
+        }
+        let mut checksum = [0; 2];
+        gdb.read_exact(&mut checksum).unwrap();
+        gdb.write_all(b"+").unwrap();
+        String::from_utf8(reply[1..].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn session() {
+        let program = assemble("
+            orth r1, 40
+            orth r2, 2
+            add r3, r1, r2
+            halt
+        ").unwrap();
+        let mut machine = Machine::with_console(Buffer::default());
+        machine.load(program);
+        let (stub, mut gdb) = UnixStream::pair().unwrap();
+        let client = std::thread::spawn(move || {
+            let mut replies = Vec::new();
+            for packet in [
+                "qSupported:swbreak+",
+                "qXfer:features:read:target.xml:0,10",
+                "?",
+                "s",
+                "p8",
+                "m0,6",
+                "Z0,8,4",
+                "c",
+                "g",
+                "P3=01000000",
+                "z0,8,4",
+                "c",
+                "bogus",
+                "D",
+            ] {
+                replies.push(exchange(&mut gdb, packet));
+            }
+            replies
+        });
+        let mut stub = Stub::new(machine, stub);
+        assert_eq!(stub.serve().unwrap(), Ending::Detached);
+        let replies = client.join().unwrap();
+        assert_eq!(replies, [
+            "PacketSize=4000;qXfer:features:read+;swbreak+",
+            "m<?xml version=\"1",
+            "S05",
+            "S05",
+            "04000000",
+            "280000d20200",
+            "OK",
+            "T05swbreak:;",
+            "0000000028000000020000000000000000000000000000000000000000000000\
+             08000000",
+            "OK",
+            "OK",
+            "W00",
+            "",
+            "OK",
+        ]);
+        assert_eq!(stub.machine().finger(), 3);
+        assert_eq!(Into::<Platter>::into(stub.machine().registers()[3.into()]), 42);
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(word(0x1234_5678), "78563412");
+        assert_eq!(words("7856341201000000"), Ok(vec![0x1234_5678, 1]));
+        assert_eq!(words("785634"), Err(()));
+        assert_eq!(transfer("abcdef", "2,2"), Ok("mcd".to_string()));
+        assert_eq!(transfer("abcdef", "4,10"), Ok("lef".to_string()));
+        assert_eq!(stop_reply(Stopped::Signal(SIGSEGV)), "S0b");
+    }
+}
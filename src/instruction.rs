@@ -1,4 +1,5 @@
-use crate::{macros::*, memory::Platter, op::Op, register, types::u25};
+use std::fmt;
+use crate::{macros::*, memory::Platter, op::{InvalidOpcode, Op, Operand}, register, types::u25};
 
 enum RegisterType {
     A(RawInstruction),
@@ -32,15 +33,18 @@ const PLATTER_SIZE: u32 = 32;
 const OP_SIZE: u32 = 4;
 // const VALUE_SIZE: u32 = 25;
 const OP_OFFSET: u32 = PLATTER_SIZE - OP_SIZE;
-impl Into<Op> for RawInstruction {
-    fn into(self) -> Op {
-        ((self.0 >> OP_OFFSET) as u8).into()
+impl TryFrom<RawInstruction> for Op {
+    type Error = InvalidOpcode;
+    fn try_from(raw: RawInstruction) -> Result<Self, Self::Error> {
+        ((raw.0 >> OP_OFFSET) as u8).try_into()
     }
 }
 
 const REG_SIZE: u32 = 3;
 const A_OFFSET: u32 = REG_SIZE * 2;
+#[allow(clippy::identity_op)]
 const B_OFFSET: u32 = REG_SIZE * 1;
+#[allow(clippy::erasing_op)]
 const C_OFFSET: u32 = REG_SIZE * 0;
 const SA_OFFSET: u32 = OP_OFFSET - REG_SIZE;
 impl From<RegisterType> for register::Index {
@@ -58,17 +62,18 @@ impl From<RegisterType> for register::Index {
     }
 }
 
-impl From<RawInstruction> for Instruction {
-    fn from(raw: RawInstruction) -> Self {
+impl TryFrom<RawInstruction> for Instruction {
+    type Error = InvalidOpcode;
+    fn try_from(raw: RawInstruction) -> Result<Self, Self::Error> {
         let raw = || raw.clone();
-        Self {
-            op: raw().into(),
+        Ok(Self {
+            op: raw().try_into()?,
             a: RegisterType::A(raw()).into(),
             b: RegisterType::B(raw()).into(),
             c: RegisterType::C(raw()).into(),
             sa: RegisterType::SA(raw()).into(),
             value: raw().into(),
-        }
+        })
     }
 }
 
@@ -98,9 +103,81 @@ impl Into<RawInstruction> for Instruction {
     }
 }
 
-impl From<Platter> for Instruction {
-    fn from(value: Platter) -> Self {
+impl TryFrom<Platter> for Instruction {
+    type Error = InvalidOpcode;
+    fn try_from(value: Platter) -> Result<Self, Self::Error> {
         let raw: RawInstruction = value.into();
-        raw.into()
+        raw.try_into()
+    }
+}
+
+impl Instruction {
+    /// The assembly form, e.g. `cmov r0, r1, r2` or `orth r3, 0x1becafe`.
+    pub fn as_pseudo_assembly(&self) -> String {
+        let operands: Vec<String> = self.op.operands().iter().map(|operand| match operand {
+            Operand::A if self.op == Op::Orth => self.sa.to_string(),
+            Operand::A => self.a.to_string(),
+            Operand::B => self.b.to_string(),
+            Operand::C => self.c.to_string(),
+            Operand::Value => {
+                let value: u32 = self.value.clone().into();
+                format!("{value:#x}")
+            }
+        }).collect();
+        match operands.is_empty() {
+            true => self.op.mnemonic().to_string(),
+            false => format!("{} {}", self.op.mnemonic(), operands.join(", ")),
+        }
+    }
+}
+
+impl Instruction {
+    /// The same instruction with the registers its assembly form leaves out
+    /// set to 0, which is how the assembler encodes it.
+    pub fn canonical(&self) -> Instruction {
+        let zero = Instruction {
+            op: self.op,
+            a: 0.into(),
+            b: 0.into(),
+            c: 0.into(),
+            sa: 0.into(),
+            value: 0.into(),
+        };
+        self.op.operands().iter().fold(zero, |mut i, operand| {
+            match operand {
+                Operand::A if self.op == Op::Orth => i.sa = self.sa,
+                Operand::A => i.a = self.a,
+                Operand::B => i.b = self.b,
+                Operand::C => i.c = self.c,
+                Operand::Value => i.value = self.value.clone(),
+            }
+            i
+        })
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_pseudo_assembly())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pseudo_assembly() {
+        let cases = [
+            (0x0000_0053u32, "cmov r1, r2, r3"),
+            (0x7000_0000, "halt"),
+            (0x8000_0011, "alloc r2, r1"),
+            (0xa000_0006, "out r6"),
+            (0xd7be_cafe, "orth r3, 0x1becafe"),
+        ];
+        for (platter, expected) in cases {
+            let i: Instruction = platter.try_into().unwrap();
+            assert_eq!(i.to_string(), expected);
+        }
     }
 }
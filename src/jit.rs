@@ -0,0 +1,686 @@
+//! Translates basic blocks of the '0' array into x86-64 code.
+//!
+//! A block is the longest run, up to `MAX_BLOCK`, of instructions that only
+//! touch the registers or the memory, starting at some finger, and may end
+//! in a Load. Everything else is left to the interpreter. So are an Amend
+//! of array 0 at an offset some block was compiled from, and a Load of any
+//! other array: the block stops before them. The interpreter then throws
+//! away the blocks compiled from the amended offset, or all of them when
+//! another array is loaded.
+//!
+//! Compiled code works on the registers where the machine keeps them, and
+//! returns how many of its instructions it completed and where to go on. A
+//! block also stops early at an instruction that would fault, so that the
+//! interpreter can fail the machine on it exactly as it would have without
+//! the JIT. A block that runs to its end goes straight on to the block
+//! compiled at the next finger, if there is one and the budget allows.
+
+use std::{ffi::c_void, io, ptr};
+use crate::{
+    decode::{DecodeCache, Decoded},
+    memory::{Memory, Platter},
+    op::Op,
+    register::{self, Registers},
+};
+
+#[cfg(not(all(target_arch = "x86_64", unix)))]
+compile_error!("the `jit` feature needs x86-64 and a unix `mmap`");
+
+/// The most instructions in one block.
+const MAX_BLOCK: usize = 32;
+/// The bytes of native code kept before all blocks are thrown away.
+const ARENA_SIZE: usize = 16 << 20;
+
+/// Compiled code: takes the registers, a `Context`, the table of blocks and
+/// the budget, and returns where it stopped.
+type Entry = unsafe extern "sysv64" fn(*mut Platter, *mut Context, *const usize, u64) -> Exit;
+
+/// What the instructions compiled to calls work on.
+struct Context<'a> {
+    memory: &'a mut Memory,
+    code: &'a mut DecodeCache,
+    covered: &'a [bool],
+    stops: &'a mut [bool],
+    blocks: &'a mut [Option<Option<Block>>],
+    /// `Jit::table`, which compiled code reads as it runs.
+    table: *mut usize,
+}
+
+/// Returned in `rax` and `rdx`.
+#[repr(C)]
+struct Exit {
+    completed: u64,
+    finger: u64,
+}
+
+#[derive(Clone, Copy)]
+struct Block {
+    entry: Entry,
+    len: u32,
+}
+
+/// The blocks compiled from the current '0' array.
+#[derive(Default)]
+pub struct Jit {
+    arena: Option<Arena>,
+    /// By finger: `None` if not compiled yet, `Some(None)` if no block
+    /// starts there.
+    blocks: Vec<Option<Option<Block>>>,
+    /// Which offsets of array 0 some block was compiled from.
+    covered: Vec<bool>,
+    /// Which offsets of array 0 some block stopped short of.
+    stops: Vec<bool>,
+    /// For compiled code to go from block to block: the number of fingers,
+    /// then by finger where the body of the block there starts, or 0.
+    table: Vec<usize>,
+}
+
+impl Jit {
+    /// Runs the block at `finger`, compiling it first if need be, and the
+    /// blocks it goes on to, for at most `budget` instructions. Returns the
+    /// instructions completed, 0 if there is no block or it is too long,
+    /// and the finger to go on from.
+    pub fn run(
+        &mut self,
+        finger: usize,
+        budget: u64,
+        code: &mut DecodeCache,
+        memory: &mut Memory,
+        registers: &mut Registers,
+    ) -> (u64, usize) {
+        let block = match self.blocks.get(finger) {
+            Some(&Some(block)) => block,
+            _ => self.compile(finger, code),
+        };
+        let Some(block) = block else { return (0, finger) };
+        let registers = registers.as_mut_ptr();
+        let table = self.table.as_mut_ptr();
+        let mut context = Context {
+            memory,
+            code,
+            covered: &self.covered,
+            stops: &mut self.stops,
+            blocks: &mut self.blocks,
+            table,
+        };
+        // SAFETY: the blocks were compiled from array 0 as it is now, and
+        // only read and write the eight registers, the table and, through
+        // the functions below, the context.
+        let exit = unsafe { (block.entry)(registers, &mut context, table, budget) };
+        (exit.completed, exit.finger as usize)
+    }
+    /// How many instructions from `finger` on, at least one, the
+    /// interpreter can run before a block may start. Only Halt, Output,
+    /// Input and invalid instructions start no block, and none of them
+    /// jumps.
+    pub fn gap(&self, finger: usize) -> u64 {
+        let blockless = self.blocks.get(finger..).unwrap_or_default().iter().take_while(|b| matches!(b, Some(None)));
+        blockless.count().max(1) as u64
+    }
+    /// Throws away the blocks compiled from `offset`, after array 0 was
+    /// amended there. Their code stays in the arena until it fills up.
+    pub fn invalidate(&mut self, offset: usize) {
+        forget(&mut self.blocks, &mut self.table, &mut self.stops, offset);
+        if !self.covered.get(offset).copied().unwrap_or(false) {
+            return;
+        }
+        self.covered[offset] = false;
+        for start in offset.saturating_sub(MAX_BLOCK - 1)..=offset {
+            if let Some(Some(block)) = self.blocks[start] {
+                if start + block.len as usize > offset {
+                    uncache(&mut self.blocks, &mut self.table, start);
+                }
+            }
+        }
+    }
+    /// Throws away every block, after array 0 was replaced.
+    pub fn flush(&mut self) {
+        self.blocks.clear();
+        self.covered.clear();
+        self.stops.clear();
+        self.table.clear();
+        if let Some(arena) = &mut self.arena {
+            arena.used = 0;
+        }
+    }
+
+    /* PRIVATE */
+    fn compile(&mut self, finger: usize, code: &DecodeCache) -> Option<Block> {
+        let program = code.as_slice();
+        let rest = program.get(finger..).unwrap_or_default();
+        // A block stops where another one starts, and goes on to it.
+        let starts = |k: usize| k > 0 && matches!(self.blocks.get(finger + k), Some(Some(Some(_))));
+        let mut instructions: Vec<Decoded> = rest
+            .iter()
+            .enumerate()
+            .map_while(|(k, &i)| i.filter(|i| compiles(i.op) && !starts(k)))
+            .take(MAX_BLOCK)
+            .collect();
+        if let Some(&Some(load)) = rest.get(instructions.len()).filter(|_| instructions.len() < MAX_BLOCK) {
+            if load.op == Op::Load {
+                instructions.push(load);
+            }
+        }
+        let block = match instructions.is_empty() {
+            true => None,
+            false => self.emit(finger, &instructions).map(|entry| Block {
+                entry,
+                len: instructions.len() as u32,
+            }),
+        };
+        // Emitting may have thrown every block away to make room.
+        if self.blocks.len() < program.len() {
+            self.blocks.resize(program.len(), None);
+            self.covered.resize(program.len(), false);
+            self.stops.resize(program.len(), false);
+            self.table.resize(program.len() + 1, 0);
+            self.table[0] = program.len();
+        }
+        let len = block.map_or(0, |b| b.len as usize);
+        if let Some(b) = block {
+            self.covered[finger..finger + len].fill(true);
+            self.table[finger + 1] = b.entry as usize + PROLOGUE.len();
+        }
+        if let Some(stop) = self.stops.get_mut(finger + len) {
+            *stop = true;
+        }
+        if let Some(slot) = self.blocks.get_mut(finger) {
+            *slot = Some(block);
+        }
+        block
+    }
+    fn emit(&mut self, finger: usize, instructions: &[Decoded]) -> Option<Entry> {
+        if self.arena.is_none() {
+            // Near the functions blocks call, for calls with a rel32.
+            self.arena = Arena::new(ARENA_SIZE, index as *const () as usize).ok();
+        }
+        let arena = self.arena.as_mut()?;
+        let start = match arena.push(&assemble(arena.next(), finger, instructions)) {
+            Some(start) => start,
+            None => {
+                self.flush();
+                let arena = self.arena.as_mut()?;
+                arena.push(&assemble(arena.next(), finger, instructions))?
+            }
+        };
+        // SAFETY: `start` holds the code `assemble` produced, which follows
+        // the sysv64 calling convention of `Entry`.
+        Some(unsafe { std::mem::transmute::<*const u8, Entry>(start) })
+    }
+}
+
+/// Whether blocks may contain instructions with operator `op` before
+/// their end.
+fn compiles(op: Op) -> bool {
+    !matches!(op, Op::Halt | Op::Output | Op::Input | Op::Load)
+}
+
+/// Looks up a platter for compiled code: stores it in `out` and returns 1,
+/// or returns 0 if Index would fault.
+extern "sysv64" fn index(context: *mut Context, array: Platter, offset: Platter, out: *mut Platter) -> u32 {
+    // SAFETY: compiled code passes on the pointers it was called with.
+    let context = unsafe { &*context };
+    match context.memory.get(array.into(), offset) {
+        Ok(value) => {
+            unsafe { *out = value };
+            1
+        }
+        Err(_) => 0,
+    }
+}
+
+/// Amends an array for compiled code and returns 1, or returns 0 if the
+/// interpreter has to do it.
+extern "sysv64" fn amend(context: *mut Context, array: Platter, offset: Platter, value: Platter) -> u32 {
+    // SAFETY: compiled code passes on the pointer it was called with.
+    let context = unsafe { &mut *context };
+    let compiled = array == 0 && context.covered.get(offset as usize).copied().unwrap_or(false);
+    if compiled || context.memory.set(array.into(), offset, value).is_err() {
+        return 0;
+    }
+    if array == 0 {
+        context.code.update(offset as usize, value);
+        // SAFETY: the table has a slot for every block, after its length.
+        let table = unsafe { std::slice::from_raw_parts_mut(context.table, context.blocks.len() + 1) };
+        forget(context.blocks, table, context.stops, offset as usize);
+    }
+    1
+}
+
+/// Forgets what the old platter at `offset` of array 0 decided, after it
+/// was amended: that no block starts there, and where blocks stopped short
+/// of it, so that they are compiled again with the new one.
+fn forget(blocks: &mut [Option<Option<Block>>], table: &mut [usize], stops: &mut [bool], offset: usize) {
+    match stops.get_mut(offset) {
+        Some(stop) if *stop => *stop = false,
+        _ => return,
+    }
+    for start in offset.saturating_sub(MAX_BLOCK)..=offset {
+        match blocks.get(start) {
+            None => break,
+            Some(Some(None)) if start == offset => uncache(blocks, table, start),
+            Some(Some(Some(block))) if start + block.len as usize == offset => uncache(blocks, table, start),
+            _ => {}
+        }
+    }
+}
+
+/// Forgets the block at `start`, so that it is compiled again when it runs
+/// next.
+fn uncache(blocks: &mut [Option<Option<Block>>], table: &mut [usize], start: usize) {
+    blocks[start] = None;
+    table[start + 1] = 0;
+}
+
+/// Allocates an array for compiled code: stores its identifier in `out`
+/// and returns 1, or returns 0 if Alloc would fault.
+extern "sysv64" fn alloc(context: *mut Context, size: Platter, out: *mut Platter) -> u32 {
+    // SAFETY: compiled code passes on the pointers it was called with.
+    let context = unsafe { &mut *context };
+    match context.memory.alloc(size as usize) {
+        Ok(array) => {
+            unsafe { *out = array.into() };
+            1
+        }
+        Err(_) => 0,
+    }
+}
+
+/// Abandons an array for compiled code and returns 1, or returns 0 if
+/// Aband would fault.
+extern "sysv64" fn aband(context: *mut Context, array: Platter) -> u32 {
+    // SAFETY: compiled code passes on the pointer it was called with.
+    let context = unsafe { &mut *context };
+    context.memory.free(array.into()).is_ok() as u32
+}
+
+/// Saves the callee-saved registers and moves the arguments of `Entry`
+/// where blocks expect them: `push rbx; push r12; push r13; push r14;
+/// push r15; mov rbx, rdi; mov r12, rsi; mov r15, rdx; mov r13, rcx;
+/// xor r14d, r14d`. Blocks go on to each other right after it.
+const PROLOGUE: [u8; 24] = [
+    0x53, 0x41, 0x54, 0x41, 0x55, 0x41, 0x56, 0x41, 0x57, 0x48, 0x89, 0xfb, 0x49, 0x89, 0xf4, 0x49, 0x89, 0xd7, 0x49,
+    0x89, 0xcd, 0x45, 0x31, 0xf6,
+];
+/// The second bytes of `jb`, `jz` and `jnz` with a rel32.
+const JB: u8 = 0x82;
+const JZ: u8 = 0x84;
+const JNZ: u8 = 0x85;
+
+/// Machine code for `instructions`, compiled from `finger` on. The
+/// registers live at `rbx`, the context pointer in `r12`, the budget left
+/// in `r13`, the instructions completed so far in `r14` and the table of
+/// blocks at `r15`, all callee-saved so that the calls into Rust keep them.
+fn assemble(at: usize, finger: usize, instructions: &[Decoded]) -> Vec<u8> {
+    let len = instructions.len() as u32;
+    let mut x = PROLOGUE.to_vec();
+    // The rel32 jumps to exits, which go after the epilogue so that the
+    // way through a block takes no branch: where each jump ends, and after
+    // how many instructions it exits.
+    let mut exits = Vec::new();
+    let mut exit = |x: &mut Vec<u8>, condition: u8, k: usize| {
+        // jcc exit
+        x.extend([0x0f, condition, 0, 0, 0, 0]);
+        exits.push((x.len(), k));
+    };
+    // cmp r13, len; jb exit
+    x.extend([0x49, 0x81, 0xfd]);
+    x.extend(len.to_le_bytes());
+    exit(&mut x, JB, 0);
+    // mov rdi, r12; call f; test eax, eax
+    let call = |x: &mut Vec<u8>, f: usize| {
+        x.extend([0x4c, 0x89, 0xe7]);
+        match i32::try_from(f as i64 - (at + x.len() + 5) as i64) {
+            Ok(rel) => {
+                x.push(0xe8);
+                x.extend(rel.to_le_bytes());
+            }
+            Err(_) => {
+                // mov rax, f; call rax
+                x.extend([0x48, 0xb8]);
+                x.extend((f as u64).to_le_bytes());
+                x.extend([0xff, 0xd0]);
+            }
+        }
+        x.extend([0x85, 0xc0]);
+    };
+    let mut jumps = false;
+    let disp = |i: register::Index| 4 * Into::<usize>::into(i) as u8;
+    for (k, i) in instructions.iter().enumerate() {
+        let (a, b, c) = (disp(i.a), disp(i.b), disp(i.c));
+        match i.op {
+            Op::Move => {
+                // mov eax, [a]; mov ecx, [c]; test ecx, ecx; cmovnz eax, [b]; mov [a], eax
+                x.extend([0x8b, 0x43, a, 0x8b, 0x4b, c, 0x85, 0xc9, 0x0f, 0x45, 0x43, b, 0x89, 0x43, a]);
+            }
+            Op::Index => {
+                // mov esi, [b]; mov edx, [c]; lea rcx, [a]
+                x.extend([0x8b, 0x73, b, 0x8b, 0x53, c, 0x48, 0x8d, 0x4b, a]);
+                call(&mut x, index as *const () as usize);
+                exit(&mut x, JZ, k);
+            }
+            Op::Amend => {
+                // mov esi, [a]; mov edx, [b]; mov ecx, [c]
+                x.extend([0x8b, 0x73, a, 0x8b, 0x53, b, 0x8b, 0x4b, c]);
+                call(&mut x, amend as *const () as usize);
+                exit(&mut x, JZ, k);
+            }
+            Op::Alloc => {
+                // mov esi, [c]; lea rdx, [b]
+                x.extend([0x8b, 0x73, c, 0x48, 0x8d, 0x53, b]);
+                call(&mut x, alloc as *const () as usize);
+                exit(&mut x, JZ, k);
+            }
+            Op::Aband => {
+                // mov esi, [c]
+                x.extend([0x8b, 0x73, c]);
+                call(&mut x, aband as *const () as usize);
+                exit(&mut x, JZ, k);
+            }
+            Op::Add => {
+                // mov eax, [b]; add eax, [c]; mov [a], eax
+                x.extend([0x8b, 0x43, b, 0x03, 0x43, c, 0x89, 0x43, a]);
+            }
+            Op::Mult => {
+                // mov eax, [b]; imul eax, [c]; mov [a], eax
+                x.extend([0x8b, 0x43, b, 0x0f, 0xaf, 0x43, c, 0x89, 0x43, a]);
+            }
+            Op::Div => {
+                // mov ecx, [c]; test ecx, ecx; jz exit
+                x.extend([0x8b, 0x4b, c, 0x85, 0xc9]);
+                exit(&mut x, JZ, k);
+                // mov eax, [b]; xor edx, edx; div ecx; mov [a], eax
+                x.extend([0x8b, 0x43, b, 0x31, 0xd2, 0xf7, 0xf1, 0x89, 0x43, a]);
+            }
+            Op::NotAnd => {
+                // mov eax, [b]; and eax, [c]; not eax; mov [a], eax
+                x.extend([0x8b, 0x43, b, 0x23, 0x43, c, 0xf7, 0xd0, 0x89, 0x43, a]);
+            }
+            Op::Orth => {
+                // mov dword [a], value
+                x.extend([0xc7, 0x43, a]);
+                x.extend(i.value.to_le_bytes());
+            }
+            Op::Load => {
+                // Only a Load of array 0 jumps within compiled code.
+                // mov ecx, [b]; test ecx, ecx; jnz exit
+                x.extend([0x8b, 0x4b, b, 0x85, 0xc9]);
+                exit(&mut x, JNZ, k);
+                // mov edx, [c]
+                x.extend([0x8b, 0x53, c]);
+                jumps = true;
+            }
+            _ => unreachable!("{:?} is not compiled", i.op),
+        }
+    }
+    if !jumps {
+        // mov edx, finger + len
+        x.push(0xba);
+        x.extend(((finger + instructions.len()) as u32).to_le_bytes());
+    }
+    // add r14, len; sub r13, len
+    x.extend([0x49, 0x81, 0xc6]);
+    x.extend(len.to_le_bytes());
+    x.extend([0x49, 0x81, 0xed]);
+    x.extend(len.to_le_bytes());
+    // Goes on to the block at the finger in rdx, if there is one:
+    // cmp rdx, [r15]; jae +12; mov rax, [r15 + 8 * rdx + 8]; test rax, rax;
+    // jz +2; jmp rax; mov rax, r14
+    x.extend([0x49, 0x3b, 0x17, 0x73, 12, 0x49, 0x8b, 0x44, 0xd7, 0x08, 0x48, 0x85, 0xc0, 0x74, 2, 0xff, 0xe0]);
+    x.extend([0x4c, 0x89, 0xf0]);
+    let epilogue = x.len();
+    // pop r15; pop r14; pop r13; pop r12; pop rbx; ret
+    x.extend([0x41, 0x5f, 0x41, 0x5e, 0x41, 0x5d, 0x41, 0x5c, 0x5b, 0xc3]);
+    for (end, k) in exits {
+        let rel = (x.len() - end) as u32;
+        x[end - 4..end].copy_from_slice(&rel.to_le_bytes());
+        // lea rax, [r14 + k]; mov edx, finger + k; jmp epilogue
+        x.extend([0x49, 0x8d, 0x86]);
+        x.extend((k as u32).to_le_bytes());
+        x.push(0xba);
+        x.extend(((finger + k) as u32).to_le_bytes());
+        x.push(0xe9);
+        x.extend(((epilogue as isize - (x.len() + 4) as isize) as i32).to_le_bytes());
+    }
+    x
+}
+
+/// The smallest page size of the hosts x86-64 unix runs on.
+const PAGE_SIZE: usize = 4096;
+const PROT_READ: i32 = 1;
+const PROT_WRITE: i32 = 2;
+const PROT_EXEC: i32 = 4;
+const MAP_PRIVATE: i32 = 0x02;
+#[cfg(target_os = "linux")]
+const MAP_ANONYMOUS: i32 = 0x20;
+#[cfg(not(target_os = "linux"))]
+const MAP_ANONYMOUS: i32 = 0x1000;
+
+extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut c_void;
+    fn mprotect(addr: *mut c_void, len: usize, prot: i32) -> i32;
+    fn munmap(addr: *mut c_void, len: usize) -> i32;
+}
+
+/// Memory for native code, writable only while code is being added.
+struct Arena {
+    base: *mut u8,
+    size: usize,
+    used: usize,
+}
+
+impl Arena {
+    /// Maps `size` bytes, if possible within reach of a rel32 from `near`.
+    fn new(size: usize, near: usize) -> io::Result<Self> {
+        let hint = near.saturating_sub(1 << 30) & !(PAGE_SIZE - 1);
+        // SAFETY: a fresh anonymous mapping aliases nothing.
+        let base = unsafe { mmap(hint as *mut c_void, size, PROT_READ | PROT_EXEC, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0) };
+        if base as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { base: base.cast(), size, used: 0 })
+    }
+    /// Where the next code pushed goes.
+    fn next(&self) -> usize {
+        self.base as usize + self.used
+    }
+    /// Copies `code` in and returns where it starts, or `None` if it does
+    /// not fit.
+    fn push(&mut self, code: &[u8]) -> Option<*const u8> {
+        if self.size - self.used < code.len() {
+            return None;
+        }
+        // Only the pages the code goes to change protection.
+        let first = self.used & !(PAGE_SIZE - 1);
+        let len = self.used + code.len() - first;
+        let protect = |prot| {
+            // SAFETY: changes the protection of the arena's own mapping.
+            unsafe { mprotect(self.base.add(first).cast(), len, prot) == 0 }
+        };
+        if !protect(PROT_READ | PROT_WRITE) {
+            return None;
+        }
+        // SAFETY: the arena is writable and has room for `code`.
+        let start = unsafe { self.base.add(self.used) };
+        unsafe { ptr::copy_nonoverlapping(code.as_ptr(), start, code.len()) };
+        if !protect(PROT_READ | PROT_EXEC) {
+            return None;
+        }
+        self.used += code.len();
+        Some(start)
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        // SAFETY: no block outlives the arena, see `Jit::flush`.
+        unsafe { munmap(self.base.cast(), self.size) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm::assemble, memory::ArrayOfPlatters};
+
+    fn run(source: &str, registers: &mut Registers, memory: &mut Memory) -> (u64, usize) {
+        let program: ArrayOfPlatters = assemble(source).unwrap().into();
+        let mut code = DecodeCache::new(&program);
+        memory.load_program(program);
+        Jit::default().run(0, u64::MAX, &mut code, memory, registers)
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut r = Registers::new();
+        r[1.into()] = 7.into();
+        r[2.into()] = 3.into();
+        r[3.into()] = 0xffff_ffff.into();
+        let program = "
+            add r4, r1, r2
+            mul r5, r1, r3
+            div r6, r1, r2
+            nand r7, r1, r2
+            cmov r0, r3, r0
+            cmov r0, r3, r1
+            orth r3, 0x1ab
+            halt
+        ";
+        assert_eq!(run(program, &mut r, &mut Memory::new()), (7, 7));
+        let values: Vec<Platter> = (0..8u32).map(|i| r[i.into()].into()).collect();
+        assert_eq!(values, [0xffff_ffff, 7, 3, 0x1ab, 10, 0xffff_fff9, 2, !(7 & 3)]);
+    }
+
+    #[test]
+    fn stops_before_faults() {
+        let mut memory = Memory::new();
+        let a = memory.alloc(2).unwrap();
+        memory.set(a, 1, 42).unwrap();
+        let mut r = Registers::new();
+        r[1.into()] = Into::<Platter>::into(a).into();
+        r[2.into()] = 1.into();
+        let program = "
+            index r3, r1, r2
+            div r4, r3, r0
+            add r5, r3, r3
+        ";
+        assert_eq!(run(program, &mut r, &mut memory), (1, 1));
+        assert_eq!(r[3.into()], 42.into());
+        let program = "add r2, r2, r2\n index r3, r1, r2";
+        assert_eq!(run(program, &mut r, &mut memory), (1, 1));
+        assert_eq!(r[2.into()], 2.into());
+    }
+
+    #[test]
+    fn allocates() {
+        let mut memory = Memory::new();
+        let mut r = Registers::new();
+        r[2.into()] = 3.into();
+        let program = "
+            alloc r1, r2
+            aband r1
+            aband r1
+        ";
+        assert_eq!(run(program, &mut r, &mut memory), (2, 2));
+        assert_eq!(r[1.into()], 1.into());
+        assert_eq!(memory.free_list(), [1.into()]);
+    }
+
+    #[test]
+    fn amends_and_jumps() {
+        let mut memory = Memory::new();
+        let a = memory.alloc(2).unwrap();
+        let mut r = Registers::new();
+        r[1.into()] = Into::<Platter>::into(a).into();
+        r[2.into()] = 1.into();
+        r[3.into()] = 42.into();
+        r[4.into()] = 3.into();
+        let program = "
+            amend r1, r2, r3
+            amend r0, r4, r3
+            load r0, r3
+            halt
+        ";
+        assert_eq!(run(program, &mut r, &mut memory), (3, 42));
+        assert_eq!(memory.get(a, 1), Ok(42));
+        assert_eq!(memory.get(0.into(), 3), Ok(42));
+        // Compiled code in array 0 and other Loads are left to the
+        // interpreter.
+        let program = "amend r0, r0, r0\n load r1, r0";
+        assert_eq!(run(program, &mut r, &mut memory), (0, 0));
+        let program = "orth r0, 0\n load r1, r0";
+        assert_eq!(run(program, &mut r, &mut memory), (1, 1));
+    }
+
+    #[test]
+    fn goes_from_block_to_block() {
+        let program: ArrayOfPlatters = assemble("
+            orth r1, 1
+            orth r3, 2
+            add r2, r2, r1
+            load r0, r3
+        ").unwrap().into();
+        let mut code = DecodeCache::new(&program);
+        let mut memory = Memory::new();
+        memory.load_program(program);
+        let mut r = Registers::new();
+        r[3.into()] = 2.into();
+        let mut jit = Jit::default();
+        assert_eq!(jit.run(2, 3, &mut code, &mut memory, &mut r), (2, 2));
+        // The block at 0 stops where the one at 2 starts, which is too
+        // long for what is left of the budget.
+        assert_eq!(jit.run(0, 3, &mut code, &mut memory, &mut r), (2, 2));
+        assert_eq!(jit.run(2, 7, &mut code, &mut memory, &mut r), (6, 2));
+        assert_eq!(r[2.into()], 3.into());
+    }
+
+    #[test]
+    fn gaps() {
+        let program: ArrayOfPlatters = assemble("out r1\n out r1\n halt\n add r1, r1, r1").unwrap().into();
+        let mut code = DecodeCache::new(&program);
+        let mut memory = Memory::new();
+        memory.load_program(program);
+        let mut r = Registers::new();
+        let mut jit = Jit::default();
+        for finger in 0..4 {
+            jit.run(finger, u64::MAX, &mut code, &mut memory, &mut r);
+        }
+        assert_eq!([0, 1, 2, 3, 4].map(|finger| jit.gap(finger)), [3, 2, 1, 1, 1]);
+    }
+
+    #[test]
+    fn recompiles_after_amend() {
+        let add = assemble("add r2, r2, r1").unwrap().as_slice()[0];
+        let program: ArrayOfPlatters = assemble("
+            amend r0, r3, r4
+            load r0, r3
+            add r2, r2, r1
+            halt
+        ").unwrap().into();
+        let mut code = DecodeCache::new(&program);
+        let mut memory = Memory::new();
+        memory.load_program(program);
+        let mut r = Registers::new();
+        r[3.into()] = 3.into();
+        r[4.into()] = add.into();
+        let mut jit = Jit::default();
+        let mut run = |jit: &mut Jit, finger, code: &mut DecodeCache, memory: &mut Memory| {
+            jit.run(finger, u64::MAX, code, memory, &mut r)
+        };
+        // Amended by the interpreter: the block at 2 was cut short by the
+        // Halt.
+        assert_eq!(run(&mut jit, 2, &mut code, &mut memory), (1, 3));
+        assert_eq!(run(&mut jit, 3, &mut code, &mut memory), (0, 3));
+        memory.set(0.into(), 3, add).unwrap();
+        code.update(3, add);
+        jit.invalidate(3);
+        assert_eq!(run(&mut jit, 2, &mut code, &mut memory), (2, 4));
+        // Amended by compiled code, after no block was found at 3.
+        memory.set(0.into(), 3, 0x7000_0000).unwrap();
+        code.update(3, 0x7000_0000);
+        jit.flush();
+        assert_eq!(run(&mut jit, 3, &mut code, &mut memory), (0, 3));
+        assert_eq!(run(&mut jit, 0, &mut code, &mut memory), (2, 3));
+        assert_eq!(run(&mut jit, 3, &mut code, &mut memory), (1, 4));
+    }
+}
@@ -0,0 +1,176 @@
+//! An undo log of what every instruction changed, so that a machine can be
+//! run backwards, see `Machine::set_journal` and `Machine::unstep`.
+//!
+//! The console cannot be undone. Instead, input that is undone is read
+//! again from the journal, and output that is undone is not written again,
+//! so running forwards once more repeats what happened the first time.
+
+use std::{collections::VecDeque, rc::Rc};
+use crate::{
+    memory::{ArrayOfPlatters, MemoryAddress, Platter},
+    op::Op,
+    register::{Index, Register},
+};
+
+/// What an instruction changed outside the registers and the finger, with
+/// what is needed to change it back.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Change {
+    Amend { array: MemoryAddress, offset: Platter, old: Platter },
+    /// `reused` if the identifier was taken off the free list.
+    Alloc { array: MemoryAddress, reused: bool },
+    Aband { array: MemoryAddress, old: Rc<ArrayOfPlatters> },
+    /// A load of an array other than '0', and the '0' array it replaced.
+    Load { old: Rc<ArrayOfPlatters> },
+    Output,
+    /// The byte read, or `None` at the end of input.
+    Input(Option<u8>),
+}
+
+/// One executed instruction.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Entry {
+    /// How many instructions were recorded before this one.
+    pub step: u64,
+    pub finger: usize,
+    pub op: Op,
+    /// The register the instruction wrote, and what it held before.
+    pub register: Option<(Index, Register)>,
+    pub change: Option<Change>,
+}
+
+/// The most recent instructions a machine executed, oldest first.
+#[derive(Debug, Default, Clone)]
+pub struct Journal {
+    entries: VecDeque<Entry>,
+    limit: Option<usize>,
+    steps: u64,
+    /// Input that was undone, to be read again last to first.
+    unread: Vec<Option<u8>>,
+    /// How much output was undone, and is not to be written again.
+    unwritten: usize,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// A journal that forgets its oldest entries beyond `limit`.
+    pub fn with_limit(limit: usize) -> Self {
+        Self { limit: Some(limit), ..Self::default() }
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    /// How many instructions were recorded, forgotten ones included, and
+    /// not undone.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = &Entry> {
+        self.entries.iter()
+    }
+    /// Forgets every entry, for a machine that was put into a new state.
+    pub fn clear(&mut self) {
+        *self = Self { limit: self.limit, ..Self::default() };
+    }
+    /// The last instruction that wrote to `offset` in the array identified
+    /// by `array`: an Amend of it, the Alloc of the array or, for the '0'
+    /// array, a Load. `None` if that happened before the oldest entry, or
+    /// if the array was abandoned since.
+    pub fn last_write(&self, array: MemoryAddress, offset: Platter) -> Option<&Entry> {
+        for entry in self.entries.iter().rev() {
+            match &entry.change {
+                Some(Change::Amend { array: a, offset: o, .. }) if *a == array && *o == offset => return Some(entry),
+                Some(Change::Alloc { array: a, .. }) if *a == array => return Some(entry),
+                Some(Change::Load { .. }) if array == 0.into() => return Some(entry),
+                Some(Change::Aband { array: a, .. }) if *a == array => return None,
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /* PRIVATE */
+    pub(crate) fn push(&mut self, entry: Entry) {
+        if self.limit.is_some_and(|limit| self.entries.len() >= limit) {
+            self.entries.pop_front();
+        }
+        if self.limit != Some(0) {
+            self.entries.push_back(entry);
+        }
+        self.steps += 1;
+    }
+    pub(crate) fn pop(&mut self) -> Option<Entry> {
+        let entry = self.entries.pop_back()?;
+        self.steps -= 1;
+        match entry.change {
+            Some(Change::Input(byte)) => self.unread.push(byte),
+            Some(Change::Output) => self.unwritten += 1,
+            _ => {}
+        }
+        Some(entry)
+    }
+    /// Input to read again instead of reading the console.
+    pub(crate) fn reread(&mut self) -> Option<Option<u8>> {
+        self.unread.pop()
+    }
+    /// Whether the next output was written already, and is to be skipped.
+    pub(crate) fn rewrite(&mut self) -> bool {
+        let skip = self.unwritten > 0;
+        self.unwritten = self.unwritten.saturating_sub(1);
+        skip
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(step: u64, change: Option<Change>) -> Entry {
+        Entry { step, finger: 0, op: Op::Amend, register: None, change }
+    }
+
+    #[test]
+    fn limit() {
+        let mut j = Journal::with_limit(2);
+        for step in 0..3 {
+            j.push(entry(step, None));
+        }
+        assert_eq!((j.len(), j.steps()), (2, 3));
+        assert_eq!(j.entries().next().map(|e| e.step), Some(1));
+        assert_eq!(j.pop().map(|e| e.step), Some(2));
+        assert_eq!(j.steps(), 2);
+    }
+
+    #[test]
+    fn last_write() {
+        let mut j = Journal::new();
+        let (one, two): (MemoryAddress, MemoryAddress) = (1.into(), 2.into());
+        j.push(entry(0, Some(Change::Alloc { array: one, reused: false })));
+        j.push(entry(1, Some(Change::Amend { array: one, offset: 3, old: 0 })));
+        j.push(entry(2, Some(Change::Amend { array: one, offset: 4, old: 0 })));
+        j.push(entry(3, Some(Change::Alloc { array: two, reused: false })));
+        assert_eq!(j.last_write(one, 3).map(|e| e.step), Some(1));
+        assert_eq!(j.last_write(one, 5).map(|e| e.step), Some(0));
+        assert_eq!(j.last_write(0.into(), 0), None);
+        j.push(entry(4, Some(Change::Aband { array: one, old: Rc::new(vec![0u32; 5].into()) })));
+        assert_eq!(j.last_write(one, 3), None);
+    }
+
+    #[test]
+    fn console() {
+        let mut j = Journal::new();
+        j.push(entry(0, Some(Change::Input(Some(b'a')))));
+        j.push(entry(1, Some(Change::Output)));
+        j.pop();
+        j.pop();
+        assert_eq!(j.reread(), Some(Some(b'a')));
+        assert_eq!(j.reread(), None);
+        assert!(j.rewrite());
+        assert!(!j.rewrite());
+    }
+}
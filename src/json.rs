@@ -0,0 +1,300 @@
+//! Just enough JSON for the Debug Adapter Protocol, see `dap`, and for
+//! exporting control-flow graphs, see `cfg`.
+
+use std::fmt;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// Members in the order they were written.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parser = Parser { text: text.as_bytes(), at: 0 };
+        let value = parser.value()?;
+        parser.space();
+        match parser.at == text.len() {
+            true => Ok(value),
+            false => Err(format!("unexpected text at byte {}", parser.at)),
+        }
+    }
+    pub fn object<const N: usize>(members: [(&str, Json); N]) -> Self {
+        Self::Object(members.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+    /// The member `key` of an object.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Self::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Self::Number(n) if n >= 0.0 && n.fract() == 0.0 => Some(n as u64),
+            _ => None,
+        }
+    }
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Self::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Self::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Self::String(s.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Self {
+        Self::String(s)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Self {
+        Self::Bool(b)
+    }
+}
+
+impl From<u64> for Json {
+    fn from(n: u64) -> Self {
+        Self::Number(n as f64)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Self {
+        Self::Number(n as f64)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(values: Vec<Json>) -> Self {
+        Self::Array(values)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => write!(f, "null"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Number(n) => write!(f, "{n}"),
+            Self::String(s) => string(s, f),
+            Self::Array(values) => {
+                write!(f, "[")?;
+                for (i, v) in values.iter().enumerate() {
+                    let comma = if i > 0 { "," } else { "" };
+                    write!(f, "{comma}{v}")?;
+                }
+                write!(f, "]")
+            }
+            Self::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    string(k, f)?;
+                    write!(f, ":{v}")?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn string(s: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    at: usize,
+}
+
+impl Parser<'_> {
+    fn space(&mut self) {
+        while self.text.get(self.at).is_some_and(u8::is_ascii_whitespace) {
+            self.at += 1;
+        }
+    }
+    fn peek(&mut self) -> Option<u8> {
+        self.space();
+        self.text.get(self.at).copied()
+    }
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        match self.peek() {
+            Some(b) if b == byte => {
+                self.at += 1;
+                Ok(())
+            }
+            _ => Err(format!("expected `{}` at byte {}", byte as char, self.at)),
+        }
+    }
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        match self.text[self.at..].starts_with(word.as_bytes()) {
+            true => {
+                self.at += word.len();
+                Ok(value)
+            }
+            false => Err(format!("unexpected text at byte {}", self.at)),
+        }
+    }
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'[') => {
+                self.at += 1;
+                let mut values = Vec::new();
+                if self.peek() == Some(b']') {
+                    self.at += 1;
+                    return Ok(Json::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    match self.peek() {
+                        Some(b',') => self.at += 1,
+                        _ => break,
+                    }
+                }
+                self.expect(b']')?;
+                Ok(Json::Array(values))
+            }
+            Some(b'{') => {
+                self.at += 1;
+                let mut members = Vec::new();
+                if self.peek() == Some(b'}') {
+                    self.at += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.space();
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    members.push((key, self.value()?));
+                    match self.peek() {
+                        Some(b',') => self.at += 1,
+                        _ => break,
+                    }
+                }
+                self.expect(b'}')?;
+                Ok(Json::Object(members))
+            }
+            Some(b'-' | b'0'..=b'9') => {
+                let start = self.at;
+                while self.text.get(self.at).is_some_and(|b| b"+-.eE0123456789".contains(b)) {
+                    self.at += 1;
+                }
+                let number = std::str::from_utf8(&self.text[start..self.at]).unwrap_or_default();
+                number.parse().map(Json::Number).map_err(|_| format!("bad number `{number}`"))
+            }
+            _ => Err(format!("expected a value at byte {}", self.at)),
+        }
+    }
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut s = Vec::new();
+        loop {
+            let byte = *self.text.get(self.at).ok_or("unterminated string")?;
+            self.at += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escape = *self.text.get(self.at).ok_or("unterminated string")?;
+                    self.at += 1;
+                    let c = match escape {
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'u' => self.unicode()?,
+                        b => b as char,
+                    };
+                    s.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                b => s.push(b),
+            }
+        }
+        String::from_utf8(s).map_err(|_| "string is not UTF-8".to_string())
+    }
+    /// The character of a `\u` escape, which may be a surrogate pair.
+    fn unicode(&mut self) -> Result<char, String> {
+        let high = self.unit()?;
+        let code = match high {
+            0xd800..=0xdbff if self.text[self.at..].starts_with(b"\\u") => {
+                self.at += 2;
+                let low = self.unit()?;
+                0x10000 + ((high - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff)
+            }
+            code => code,
+        };
+        Ok(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+    /// The four hex digits of a UTF-16 code unit.
+    fn unit(&mut self) -> Result<u32, String> {
+        let hex = self.text.get(self.at..self.at + 4).ok_or("short \\u escape")?;
+        self.at += 4;
+        let hex = std::str::from_utf8(hex).map_err(|e| e.to_string())?;
+        u32::from_str_radix(hex, 16).map_err(|_| format!("bad \\u escape `{hex}`"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let text = r#"{"seq":1,"arguments":{"lines":[1,-2.5e1],"ok":true,"none":null},"s":"a\"\\\né😀"}"#;
+        let json = Json::parse(text).unwrap();
+        assert_eq!(json.get("seq").and_then(Json::as_u64), Some(1));
+        assert_eq!(json.get("arguments").and_then(|a| a.get("lines")), Some(&Json::Array(vec![
+            Json::Number(1.0),
+            Json::Number(-25.0),
+        ])));
+        assert_eq!(json.get("s").and_then(Json::as_str), Some("a\"\\\né😀"));
+        assert_eq!(Json::parse(&json.to_string()), Ok(json));
+        assert_eq!(Json::object([("a", "\u{1}".into())]).to_string(), r#"{"a":"\u0001"}"#);
+        assert!(Json::parse("[1,]").is_err());
+        assert!(Json::parse("{} x").is_err());
+    }
+}
@@ -0,0 +1,53 @@
+//! An implementation of the Universal Machine described in `um-spec.txt`.
+//!
+//! ```no_run
+//! use um::{Machine, Program};
+//!
+//! let source = std::fs::read("sandmark.umz").unwrap();
+//! let program: Program = source.into();
+//! let mut machine = Machine::new();
+//! machine.load(program);
+//! machine.run().unwrap();
+//! ```
+
+// The newtypes in this crate convert into their underlying types with
+// `impl Into`, mirroring the `impl From` in the other direction.
+#![allow(clippy::from_over_into)]
+
+pub mod asm;
+pub mod cfg;
+pub mod compile;
+pub mod console;
+pub mod dap;
+pub mod debugger;
+pub mod decode;
+pub mod disasm;
+pub mod fault;
+pub mod gdb;
+pub mod instruction;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod journal;
+mod json;
+pub mod lockstep;
+pub mod machine;
+mod macros;
+pub mod memory;
+pub mod op;
+pub mod profile;
+pub mod program;
+pub mod register;
+pub mod selftest;
+pub mod snapshot;
+pub mod trace;
+pub mod types;
+pub mod watch;
+
+pub use console::{Buffer, Console, Scripted, Stdio, StopAtEof};
+pub use fault::{Fault, FaultKind};
+pub use instruction::{Instruction, RawInstruction};
+pub use machine::{Engine, Limits, Machine, RunOutcome, StepOutcome};
+pub use memory::{ArrayOfPlatters, Memory, MemoryAddress, Platter, Quota};
+pub use op::Op;
+pub use program::Program;
+pub use register::{Register, Registers};
@@ -0,0 +1,338 @@
+//! Runs one program on two engines side by side, to catch them disagreeing.
+//!
+//! Both machines run a slice of instructions at a time, after which their
+//! outcomes, execution fingers, registers, output and memory are compared.
+//! When a slice ends with the machines apart, both go back to its start and
+//! the slice is bisected down to the first instruction after which they
+//! differ. Slices are run whole rather than stepped, so that an engine that
+//! works on blocks of instructions runs them as it normally would.
+//!
+//! Both machines share a console: whichever reads a byte first takes it
+//! from the console, and the other is handed the same byte. Likewise,
+//! whichever writes a byte of output first writes it to the console, and
+//! the other is told how that went.
+
+use std::{cell::RefCell, fmt, io, rc::Rc};
+use crate::{
+    console::Console,
+    machine::{Engine, Limits, Machine, RunOutcome},
+    memory::Memory,
+    program::Program,
+    snapshot::Snapshot,
+};
+
+/// How many instructions run between comparisons, unless set otherwise.
+pub const SLICE: u64 = 100_000;
+/// How many differences in memory a `Divergence` lists.
+const MEMORY_DIFFERENCES: usize = 10;
+
+/// What went through the console so far, shared by both machines.
+struct Tape<C> {
+    console: C,
+    input: Vec<Option<u8>>,
+    /// For each byte of output, whether the console took it.
+    output: Vec<Result<(), io::ErrorKind>>,
+}
+
+/// The console of a machine running in lockstep.
+pub struct Replay<C> {
+    tape: Rc<RefCell<Tape<C>>>,
+    /// How many bytes of the tape this machine read.
+    read: usize,
+    pub output: Vec<u8>,
+}
+
+impl<C: Console> Console for Replay<C> {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut tape = self.tape.borrow_mut();
+        if self.read == tape.input.len() {
+            let byte = tape.console.read_byte()?;
+            tape.input.push(byte);
+        }
+        self.read += 1;
+        Ok(tape.input[self.read - 1])
+    }
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        let mut tape = self.tape.borrow_mut();
+        if self.output.len() == tape.output.len() {
+            let written = tape.console.write_byte(byte).map_err(|e| e.kind());
+            tape.output.push(written);
+        }
+        tape.output[self.output.len()]?;
+        self.output.push(byte);
+        Ok(())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.tape.borrow_mut().console.flush()
+    }
+}
+
+/// Where both machines stood at the start of a slice.
+struct Checkpoint {
+    snapshots: [Snapshot; 2],
+    read: [usize; 2],
+    output: [usize; 2],
+}
+
+pub struct Lockstep<C> {
+    machines: [Machine<Replay<C>>; 2],
+    /// Instructions completed by both machines.
+    steps: u64,
+    slice: u64,
+}
+
+impl<C: Console> Lockstep<C> {
+    /// Two machines running `program` on `engines`, talking to `console`.
+    pub fn new(program: Program, engines: [Engine; 2], console: C) -> Self {
+        let tape = Rc::new(RefCell::new(Tape { console, input: Vec::new(), output: Vec::new() }));
+        let mut machines = [(), ()].map(|()| {
+            Machine::with_console(Replay { tape: Rc::clone(&tape), read: 0, output: Vec::new() })
+        });
+        machines[0].load(program);
+        let start = machines[0].snapshot();
+        machines[1].restore(start);
+        for (machine, engine) in machines.iter_mut().zip(engines) {
+            machine.set_engine(engine);
+        }
+        Self { machines, steps: 0, slice: SLICE }
+    }
+    pub fn machines(&self) -> &[Machine<Replay<C>>; 2] {
+        &self.machines
+    }
+    pub fn machines_mut(&mut self) -> &mut [Machine<Replay<C>>; 2] {
+        &mut self.machines
+    }
+    /// Compares the machines every `slice` instructions.
+    pub fn set_slice(&mut self, slice: u64) {
+        self.slice = slice.max(1);
+    }
+    /// Runs both machines until they stop, or for `max_steps` instructions
+    /// in all, and returns how they stopped. Fails with where they came
+    /// apart if they do.
+    pub fn run(&mut self, max_steps: Option<u64>) -> Result<RunOutcome, Box<Divergence>> {
+        loop {
+            let n = max_steps.map_or(self.slice, |max| max.saturating_sub(self.steps).min(self.slice));
+            if n == 0 {
+                return Ok(RunOutcome::BudgetExhausted);
+            }
+            let checkpoint = self.checkpoint();
+            let outcomes = self.advance(n);
+            if !self.agree(&outcomes) {
+                return Err(self.bisect(&checkpoint, n));
+            }
+            match outcomes {
+                [RunOutcome::BudgetExhausted, _] => self.steps += n,
+                [outcome, _] => return Ok(outcome),
+            }
+        }
+    }
+    /// Hands back the console once both machines are done with it.
+    pub fn into_console(self) -> C {
+        let [a, b] = self.machines;
+        let tape = Rc::clone(&a.console().tape);
+        drop((a, b));
+        match Rc::try_unwrap(tape) {
+            Ok(tape) => tape.into_inner().console,
+            Err(_) => unreachable!("only the machines share the tape"),
+        }
+    }
+
+    /* PRIVATE */
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            snapshots: self.machines.each_ref().map(Machine::snapshot),
+            read: self.machines.each_ref().map(|m| m.console().read),
+            output: self.machines.each_ref().map(|m| m.console().output.len()),
+        }
+    }
+    fn rewind(&mut self, checkpoint: &Checkpoint) {
+        for (i, machine) in self.machines.iter_mut().enumerate() {
+            machine.restore(checkpoint.snapshots[i].clone());
+            let console = machine.console_mut();
+            console.read = checkpoint.read[i];
+            console.output.truncate(checkpoint.output[i]);
+        }
+    }
+    fn advance(&mut self, n: u64) -> [RunOutcome; 2] {
+        let limits = Limits { max_steps: Some(n), deadline: None };
+        self.machines.each_mut().map(|m| m.run_limited(limits))
+    }
+    fn agree(&self, outcomes: &[RunOutcome; 2]) -> bool {
+        let [a, b] = &self.machines;
+        outcomes[0] == outcomes[1]
+            && a.finger() == b.finger()
+            && a.registers() == b.registers()
+            && a.console().output == b.console().output
+            && a.memory() == b.memory()
+    }
+    /// Finds the first of the `n` instructions after `checkpoint` after
+    /// which the machines differ, knowing that they differ after all `n`.
+    fn bisect(&mut self, checkpoint: &Checkpoint, n: u64) -> Box<Divergence> {
+        let (mut same, mut apart) = (0, n);
+        while apart - same > 1 {
+            let mid = same + (apart - same) / 2;
+            self.rewind(checkpoint);
+            match self.advance(mid) {
+                outcomes if self.agree(&outcomes) => same = mid,
+                _ => apart = mid,
+            }
+        }
+        self.rewind(checkpoint);
+        self.advance(same);
+        let [a, _] = &self.machines;
+        let last = (a.finger(), a.peek().ok().map_or(String::new(), |i| i.to_string()));
+        self.rewind(checkpoint);
+        let outcomes = self.advance(apart);
+        let [a, b] = &self.machines;
+        Box::new(Divergence {
+            steps: self.steps + apart,
+            last,
+            engines: [a.engine(), b.engine()],
+            outcomes,
+            snapshots: [a.snapshot(), b.snapshot()],
+            outputs: [a.console().output.clone(), b.console().output.clone()],
+        })
+    }
+}
+
+/// The states of two machines that ran the same instructions and came out
+/// differently.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Divergence {
+    /// Instructions run, the last of which set the machines apart.
+    pub steps: u64,
+    /// The finger and disassembly of that last instruction.
+    pub last: (usize, String),
+    pub engines: [Engine; 2],
+    pub outcomes: [RunOutcome; 2],
+    pub snapshots: [Snapshot; 2],
+    pub outputs: [Vec<u8>; 2],
+}
+
+impl Divergence {
+    /// What differs, as rows of a name and the two values.
+    pub fn differences(&self) -> Vec<[String; 3]> {
+        let mut rows = Vec::new();
+        let mut row = |name: String, a: String, b: String| rows.push([name, a, b]);
+        let [a, b] = &self.snapshots;
+        if self.outcomes[0] != self.outcomes[1] {
+            row("outcome".to_string(), outcome(&self.outcomes[0]), outcome(&self.outcomes[1]));
+        }
+        if a.finger != b.finger {
+            row("finger".to_string(), a.finger.to_string(), b.finger.to_string());
+        }
+        for i in 0..8u32 {
+            let (x, y): (u32, u32) = (a.registers[i.into()].into(), b.registers[i.into()].into());
+            if x != y {
+                row(format!("r{i}"), format!("{x:#010x}"), format!("{y:#010x}"));
+            }
+        }
+        let [x, y] = &self.outputs;
+        if let Some(at) = (0..x.len().max(y.len())).find(|&i| x.get(i) != y.get(i)) {
+            let byte = |o: &Vec<u8>| o.get(at).map_or("end".to_string(), |b| format!("{:?}", *b as char));
+            row(format!("output byte {at}"), byte(x), byte(y));
+        }
+        rows.extend(memory_differences(&a.memory, &b.memory));
+        rows
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b] = self.engines.map(|e| format!("{e:?}"));
+        let (finger, instruction) = &self.last;
+        writeln!(f, "{a} and {b} diverge after {} instructions, the last at finger {finger}: {instruction}", self.steps)?;
+        write!(f, "{:<19} {a:<31} {b}", "")?;
+        for [name, x, y] in self.differences() {
+            write!(f, "\n{name:<19} {x:<31} {y}")?;
+        }
+        Ok(())
+    }
+}
+
+fn outcome(o: &RunOutcome) -> String {
+    match o {
+        RunOutcome::Halted => "halted".to_string(),
+        RunOutcome::BudgetExhausted => "running".to_string(),
+        RunOutcome::WaitingForInput => "waiting for input".to_string(),
+        RunOutcome::Watchpoint(hit) => format!("stopped at watchpoint {}", hit.watchpoint),
+        RunOutcome::Faulted(fault) => format!("failed: {}", fault.kind),
+    }
+}
+
+/// The first `MEMORY_DIFFERENCES` differences between `a` and `b`, as rows.
+fn memory_differences(a: &Memory, b: &Memory) -> Vec<[String; 3]> {
+    let (x, y): (Vec<_>, Vec<_>) = (a.slots().collect(), b.slots().collect());
+    let active = |s: Option<_>| if s.is_some() { "active" } else { "inactive" }.to_string();
+    let mut rows: Vec<[String; 3]> = (0..x.len().max(y.len()))
+        .filter_map(|i| {
+            let (p, q) = (x.get(i).copied().flatten(), y.get(i).copied().flatten());
+            match (p, q) {
+                (Some(p), Some(q)) if p.len() != q.len() => {
+                    Some([format!("array {i} length"), p.len().to_string(), q.len().to_string()])
+                }
+                (Some(p), Some(q)) => {
+                    let at = (0..p.len()).find(|&o| p[o] != q[o])?;
+                    Some([format!("array {i}[{at}]"), format!("{:#010x}", p[at]), format!("{:#010x}", q[at])])
+                }
+                (None, None) => None,
+                _ => Some([format!("array {i}"), active(p), active(q)]),
+            }
+        })
+        .take(MEMORY_DIFFERENCES)
+        .collect();
+    if a.free_list() != b.free_list() {
+        let list = |m: &Memory| format!("{} arrays", m.free_list().len());
+        rows.push(["free list".to_string(), list(a), list(b)]);
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm::assemble, console::Buffer, memory::Quota};
+
+    #[test]
+    fn agree() {
+        let program = assemble("
+            in r1
+            out r1
+            in r1
+            out r1
+            halt
+        ").unwrap();
+        let mut lockstep = Lockstep::new(program, [Engine::Reference, Engine::Fast], Buffer::new(*b"hi"));
+        lockstep.set_slice(3);
+        assert_eq!(lockstep.run(None), Ok(RunOutcome::Halted));
+        assert_eq!(lockstep.machines()[1].console().output, b"hi");
+        assert_eq!(lockstep.into_console().output, b"hi");
+    }
+
+    #[test]
+    fn diverge() {
+        let program = assemble("
+            orth r2, 100
+            orth r3, 1
+            add r4, r3, r3
+            alloc r1, r2
+            halt
+        ").unwrap();
+        let mut lockstep = Lockstep::new(program, [Engine::Reference, Engine::Fast], Buffer::default());
+        lockstep.machines_mut()[1].set_quota(Quota { max_array: Some(10), ..Quota::default() });
+        let divergence = lockstep.run(None).unwrap_err();
+        assert_eq!(divergence.steps, 4);
+        assert_eq!(divergence.last, (3, "alloc r1, r2".to_string()));
+        assert_eq!(
+            divergence.differences(),
+            [
+                ["outcome", "running", "failed: out of memory for an array of 100 platters"],
+                ["finger", "4", "3"],
+                ["r1", "0x00000001", "0x00000000"],
+                ["array 1", "active", "inactive"],
+            ]
+            .map(|row| row.map(String::from))
+        );
+        assert!(divergence.to_string().starts_with("Reference and Fast diverge after 4 instructions, the last at finger 3: alloc r1, r2\n"));
+    }
+}
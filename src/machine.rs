@@ -1,53 +1,661 @@
-use core::panic;
-use std::{io::Read, process::exit};
+use std::{collections::BTreeMap, io};
+use std::time::Instant;
+use crate::console::{Console, Stdio};
+use crate::decode::{DecodeCache, Decoded};
+use crate::fault::{Fault, FaultKind};
+use crate::journal::{Change, Entry, Journal};
 use crate::op::Op;
-use crate::register::Registers;
+use crate::register::{self, Register, Registers, NUMBER_OF_REGISTERS};
 use crate::program::Program;
-use crate::memory::{Memory, MemoryAddress, Platter};
+use crate::memory::{Memory, MemoryAddress, Platter, Quota};
 use crate::instruction::Instruction;
+use crate::snapshot::Snapshot;
+use crate::trace::{Effect, Event, Tracer};
+use crate::watch::{Hit, Target, Watchpoint};
 
-pub struct Machine {
+/// A Universal Machine: eight registers, the execution finger, the
+/// arrays of platters it operates on and the console it talks to.
+pub struct Machine<C = Stdio> {
     mem: Memory, // program "array 0"
     ip: usize,
     r: Registers,
+    console: C,
+    code: DecodeCache, // decoded "array 0"
+    tracer: Option<Box<dyn Tracer>>,
+    journal: Option<Journal>,
+    watchpoints: BTreeMap<usize, Watchpoint>,
+    next_watchpoint: usize,
+    engine: Engine,
+    #[cfg(feature = "jit")]
+    jit: crate::jit::Jit,
 }
 
+/// How `Machine::run` and `Machine::run_limited` execute instructions.
+/// All engines behave the same, down to faults and console flushes.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Engine {
+    /// One `Machine::step` at a time.
+    Reference,
+    /// Straight over the decode cache, with the registers held in locals.
+    /// Runs with a tracer set use the reference engine anyway.
+    #[default]
+    Fast,
+    /// Compiles blocks of array 0 to native code and runs them, leaving
+    /// the rest to the fast engine. Runs with a tracer set use the
+    /// reference engine anyway.
+    #[cfg(feature = "jit")]
+    Jit,
+}
+
+/// What became of the machine after executing one instruction.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StepOutcome {
+    Running,
+    /// The machine stopped computation. The execution finger stays on the
+    /// Halt instruction, so stepping again halts again.
+    Halted,
+    /// The instruction ran and triggered a watchpoint.
+    Watchpoint(Hit),
+}
+
+/// How far `Machine::run_limited` may go before handing control back.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Limits {
+    /// The most instructions to execute.
+    pub max_steps: Option<u64>,
+    /// When to stop, checked every `DEADLINE_INTERVAL` instructions.
+    pub deadline: Option<Instant>,
+}
+
+/// Why `Machine::run_limited` handed control back.
+#[derive(Debug, PartialEq, Clone)]
+pub enum RunOutcome {
+    Halted,
+    /// The step budget ran out or the deadline passed. Running again
+    /// continues from the instruction under the execution finger.
+    BudgetExhausted,
+    /// The console has no input yet, see `console::StopAtEof`. The execution
+    /// finger stays on the Input instruction, so running again retries it.
+    WaitingForInput,
+    /// An instruction triggered a watchpoint. Running again continues
+    /// after it.
+    Watchpoint(Hit),
+    Faulted(Fault),
+}
+
+/// How many instructions `Machine::run_limited` executes between looks at
+/// the clock.
+pub const DEADLINE_INTERVAL: u64 = 4096;
+
 impl Machine {
     /* PUBLIC */
     pub fn new() -> Self {
+        Self::with_console(Stdio::new())
+    }
+}
+
+impl<C: Console> Machine<C> {
+    /* PUBLIC */
+    pub fn with_console(console: C) -> Self {
         Self {
             mem: Memory::new(), // program "array 0"
             ip: 0,
             r: Registers::new(),
+            console,
+            code: DecodeCache::default(),
+            tracer: None,
+            journal: None,
+            watchpoints: BTreeMap::new(),
+            next_watchpoint: 1,
+            engine: Engine::default(),
+            #[cfg(feature = "jit")]
+            jit: Default::default(),
         }
     }
     pub fn load(&mut self, program: Program) {
-        if self.mem.len() < 1 {
-            self.mem.alloc(0);
-        }
-        let zero_addr: MemoryAddress = 0.into();
-        self.mem[zero_addr] = program.into();
+        self.mem.load_program(program.into());
+        self.decode();
+        self.forget();
     }
-    pub fn run(&mut self) {
+    /// Runs until the machine halts or Fails, passing any watchpoints.
+    pub fn run(&mut self) -> Result<(), Fault> {
+        if self.sprints() {
+            while self.sprint(u64::MAX)? != StepOutcome::Halted {}
+            return Ok(());
+        }
         loop {
             // print!("{}\t| ", self.ip);
-            self.act();
+            if self.act()? == StepOutcome::Halted {
+                return Ok(());
+            }
             // std::thread::sleep(std::time::Duration::from_millis(50));
         }
     }
+    /// Runs until the machine halts, fails, waits for input or goes past
+    /// `limits`.
+    pub fn run_limited(&mut self, limits: Limits) -> RunOutcome {
+        let sprints = self.sprints();
+        let max_steps = limits.max_steps.unwrap_or(u64::MAX);
+        let mut steps = 0u64;
+        loop {
+            let out_of_steps = steps >= max_steps;
+            let out_of_time = steps.is_multiple_of(DEADLINE_INTERVAL)
+                && limits.deadline.is_some_and(|deadline| Instant::now() >= deadline);
+            if out_of_steps || out_of_time {
+                return match self.flush() {
+                    Ok(()) => RunOutcome::BudgetExhausted,
+                    Err(kind) => RunOutcome::Faulted(self.fault(kind)),
+                };
+            }
+            // Up to the next look at the clock, or the end of the budget.
+            let n = match sprints {
+                true => (DEADLINE_INTERVAL - steps % DEADLINE_INTERVAL).min(max_steps - steps),
+                false => 1,
+            };
+            let outcome = match sprints {
+                true => self.sprint(n),
+                false => self.act(),
+            };
+            match outcome {
+                Ok(StepOutcome::Running) => steps += n,
+                Ok(StepOutcome::Halted) => return RunOutcome::Halted,
+                Ok(StepOutcome::Watchpoint(hit)) => return RunOutcome::Watchpoint(hit),
+                Err(fault) if fault.kind == FaultKind::Console(io::ErrorKind::WouldBlock) => {
+                    return RunOutcome::WaitingForInput
+                }
+                Err(fault) => return RunOutcome::Faulted(fault),
+            }
+        }
+    }
+    /// Executes the instruction under the execution finger. On a fault the
+    /// finger is left on the faulting instruction.
+    pub fn step(&mut self) -> Result<StepOutcome, Fault> {
+        self.act()
+    }
+    /// Decodes the instruction under the execution finger without executing it.
+    pub fn peek(&self) -> Result<Instruction, Fault> {
+        self.fetch().map_err(|kind| Fault {
+            kind,
+            finger: self.ip,
+            instruction: None,
+        })
+    }
+    /// The offset into array 0 of the next instruction to execute.
+    pub fn finger(&self) -> usize {
+        self.ip
+    }
+    /// Moves the execution finger, e.g. for a debugger jumping elsewhere.
+    /// Moves the finger, and clears the journal, which could not undo what
+    /// came before.
+    pub fn set_finger(&mut self, finger: usize) {
+        self.forget();
+        self.ip = finger;
+    }
+    pub fn registers(&self) -> &Registers {
+        &self.r
+    }
+    /// The registers to change, which clears the journal like `set_finger`.
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        self.forget();
+        &mut self.r
+    }
+    pub fn memory(&self) -> &Memory {
+        &self.mem
+    }
+    /// Stores `value` at `offset` in the array identified by `array`, as an
+    /// Amend would, and clears the journal like `set_finger`.
+    pub fn amend(&mut self, array: MemoryAddress, offset: Platter, value: Platter) -> Result<(), FaultKind> {
+        self.mem.set(array, offset, value)?;
+        self.forget();
+        if array == 0.into() {
+            self.code.update(offset as usize, value);
+            #[cfg(feature = "jit")]
+            self.jit.invalidate(offset as usize);
+        }
+        Ok(())
+    }
+    /// Limits what the program may allocate from now on.
+    pub fn set_quota(&mut self, quota: Quota) {
+        self.mem.set_quota(quota);
+    }
+    /// The state of the machine, apart from its console.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            finger: self.ip,
+            registers: self.r.clone(),
+            memory: self.mem.clone(),
+        }
+    }
+    /// Puts the machine back into the state of `snapshot`, keeping its quota.
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.ip = snapshot.finger;
+        self.r = snapshot.registers;
+        let quota = self.mem.quota();
+        self.mem = snapshot.memory;
+        self.mem.set_quota(quota);
+        self.decode();
+        self.forget();
+    }
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+    }
+    /// Records every instruction the machine completes from now on.
+    pub fn set_tracer(&mut self, tracer: Box<dyn Tracer>) {
+        self.tracer = Some(tracer);
+    }
+    /// Stops tracing and hands back the tracer.
+    pub fn take_tracer(&mut self) -> Option<Box<dyn Tracer>> {
+        self.tracer.take()
+    }
+    /// Records what every instruction the machine completes from now on
+    /// changes in `journal`, so that `unstep` can undo it. The machine runs
+    /// one instruction at a time meanwhile, like the reference engine.
+    pub fn set_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+    /// Stops recording and hands back the journal.
+    pub fn take_journal(&mut self) -> Option<Journal> {
+        self.journal.take()
+    }
+    /// Undoes the last instruction in the journal, and returns whether there
+    /// was one. Fails, clearing the journal, if memory no longer matches it.
+    pub fn unstep(&mut self) -> Result<bool, FaultKind> {
+        let Some(entry) = self.journal.as_mut().and_then(Journal::pop) else {
+            return Ok(false);
+        };
+        match entry.change {
+            Some(Change::Amend { array, offset, old }) => {
+                if let Err(kind) = self.mem.set(array, offset, old) {
+                    self.forget();
+                    return Err(kind);
+                }
+                if array == 0.into() {
+                    self.code.update(offset as usize, old);
+                    #[cfg(feature = "jit")]
+                    self.jit.invalidate(offset as usize);
+                }
+            }
+            Some(Change::Alloc { array, reused }) => self.mem.unalloc(array, reused),
+            Some(Change::Aband { array, old }) => self.mem.unfree(array, old),
+            Some(Change::Load { old }) => {
+                self.mem.unload(old);
+                self.decode();
+            }
+            Some(Change::Output | Change::Input(_)) | None => {}
+        }
+        if let Some((register, value)) = entry.register {
+            self.r[register] = value;
+        }
+        self.ip = entry.finger;
+        Ok(true)
+    }
+    /// Stops the machine whenever an instruction touches what `watchpoint`
+    /// watches from now on, and returns its number. The machine runs one
+    /// instruction at a time meanwhile, like the reference engine.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        let n = self.next_watchpoint;
+        self.next_watchpoint += 1;
+        self.watchpoints.insert(n, watchpoint);
+        n
+    }
+    pub fn remove_watchpoint(&mut self, n: usize) -> Option<Watchpoint> {
+        self.watchpoints.remove(&n)
+    }
+    pub fn watchpoints(&self) -> impl Iterator<Item = (usize, &Watchpoint)> {
+        self.watchpoints.iter().map(|(&n, w)| (n, w))
+    }
+    pub fn console(&self) -> &C {
+        &self.console
+    }
+    pub fn console_mut(&mut self) -> &mut C {
+        &mut self.console
+    }
+    pub fn into_console(self) -> C {
+        self.console
+    }
 
     /* PRIVATE */
-    fn peek(&self) -> Instruction {
+    fn fetch(&self) -> Result<Instruction, FaultKind> {
+        let zero_addr: MemoryAddress = 0.into();
+        let platter = Platter::try_from(self.ip)
+            .ok()
+            .and_then(|offset| self.mem.get(zero_addr, offset).ok())
+            .ok_or(FaultKind::FingerOutOfRange)?;
+        Ok(platter.try_into()?)
+    }
+    fn fault(&self, kind: FaultKind) -> Fault {
+        Fault {
+            kind,
+            finger: self.ip,
+            instruction: self.fetch().ok(),
+        }
+    }
+    fn decode(&mut self) {
         let zero_addr: MemoryAddress = 0.into();
-        self.mem[zero_addr][self.ip].into()
+        self.code = DecodeCache::new(&self.mem[zero_addr]);
+        #[cfg(feature = "jit")]
+        self.jit.flush();
+    }
+    fn next(&mut self) -> Result<Decoded, Fault> {
+        match self.code.get(self.ip) {
+            Some(instruction) => {
+                self.ip += 1;
+                Ok(instruction)
+            }
+            None => Err(self.peek().expect_err("decode cache out of sync with array 0")),
+        }
+    }
+    fn act(&mut self) -> Result<StepOutcome, Fault> {
+        let outcome = self.cycle();
+        self.settle(outcome)
     }
-    fn next(&mut self) -> Instruction {
-        let instruction = self.peek();
-        self.ip += 1;
-        instruction
+    /// Flushes once the machine stops running.
+    fn settle(&mut self, outcome: Result<StepOutcome, Fault>) -> Result<StepOutcome, Fault> {
+        if outcome != Ok(StepOutcome::Running) {
+            let flushed = self.flush();
+            if outcome.is_ok() {
+                flushed.map_err(|kind| self.fault(kind))?;
+            }
+        }
+        outcome
     }
-    fn act(&mut self) {
-        let i = self.next();
+    /// Flushes the console and the tracer.
+    fn flush(&mut self) -> Result<(), FaultKind> {
+        let console = self.console.flush().map_err(FaultKind::from);
+        let tracer = match &mut self.tracer {
+            Some(tracer) => tracer.flush().map_err(|e| FaultKind::Trace(e.kind())),
+            None => Ok(()),
+        };
+        console.and(tracer)
+    }
+    fn cycle(&mut self) -> Result<StepOutcome, Fault> {
+        let finger = self.ip;
+        let i = self.next()?;
+        let traced = match &self.tracer {
+            Some(tracer) if tracer.wants(finger, i.op) => {
+                Some((self.mem.get(0.into(), finger as Platter), self.r.clone()))
+            }
+            _ => None,
+        };
+        let undo = self.journal.is_some().then(|| (self.r.clone(), self.undo(i)));
+        let watched = (!self.watchpoints.is_empty()).then(|| (self.r.clone(), self.abandoning(i)));
+        let mut outcome = self.execute(i);
+        if outcome != Ok(StepOutcome::Running) {
+            self.ip = finger;
+        }
+        if let (Some((before, change)), Ok(StepOutcome::Running)) = (undo, &outcome) {
+            self.record(finger, i, before, change);
+        }
+        if let (Some((before, size)), Ok(StepOutcome::Running)) = (watched, &outcome) {
+            if let Some(hit) = self.watch(finger, i, &before, size) {
+                outcome = Ok(StepOutcome::Watchpoint(hit));
+            }
+        }
+        if let (Some((Ok(platter), before)), Ok(_)) = (traced, &outcome) {
+            self.trace(finger, platter, before).map_err(|kind| self.fault(kind))?;
+        }
+        outcome.map_err(|kind| self.fault(kind))
+    }
+    fn trace(&mut self, finger: usize, platter: Platter, before: Registers) -> Result<(), FaultKind> {
+        let instruction = Instruction::try_from(platter)?;
+        let event = Event {
+            finger,
+            platter,
+            effect: Effect::of(&instruction, &before, &self.r),
+            instruction,
+            before,
+            after: self.r.clone(),
+        };
+        match &mut self.tracer {
+            Some(tracer) => tracer.record(&event).map_err(|e| FaultKind::Trace(e.kind())),
+            None => Ok(()),
+        }
+    }
+    /// Clears the journal, which no longer applies.
+    fn forget(&mut self) {
+        if let Some(journal) = &mut self.journal {
+            journal.clear();
+        }
+    }
+    /// What the journal needs to undo `i`, taken before it runs. Allocations
+    /// and input are completed by `record`.
+    fn undo(&self, i: Decoded) -> Option<Change> {
+        let r = &self.r;
+        match i.op {
+            Op::Amend => {
+                let (array, offset) = (r[i.a].into(), r[i.b].into());
+                let old = self.mem.get(array, offset).ok()?;
+                Some(Change::Amend { array, offset, old })
+            }
+            Op::Alloc => Some(Change::Alloc { array: 0.into(), reused: !self.mem.free_list().is_empty() }),
+            Op::Aband => {
+                let array = r[i.c].into();
+                Some(Change::Aband { array, old: self.mem.shared(array)? })
+            }
+            Op::Load if r[i.b] != 0.into() => Some(Change::Load { old: self.mem.shared(0.into())? }),
+            Op::Output => Some(Change::Output),
+            Op::Input => Some(Change::Input(None)),
+            _ => None,
+        }
+    }
+    /// Adds the instruction `i` at `finger` to the journal, once it ran.
+    fn record(&mut self, finger: usize, i: Decoded, before: Registers, change: Option<Change>) {
+        let change = match change {
+            Some(Change::Alloc { reused, .. }) => Some(Change::Alloc { array: self.r[i.b].into(), reused }),
+            Some(Change::Input(_)) => Some(Change::Input(u8::try_from(Into::<Platter>::into(self.r[i.c])).ok())),
+            change => change,
+        };
+        let register = (0..NUMBER_OF_REGISTERS as u32)
+            .map(register::Index::from)
+            .find(|&k| before[k] != self.r[k])
+            .map(|k| (k, before[k]));
+        if let Some(journal) = &mut self.journal {
+            let step = journal.steps();
+            journal.push(Entry { step, finger, op: i.op, register, change });
+        }
+    }
+    /// The size of the array `i` abandons, if it does.
+    fn abandoning(&self, i: Decoded) -> Option<usize> {
+        match i.op {
+            Op::Aband => self.mem.array(self.r[i.c].into()).map(|array| array.len()).ok(),
+            _ => None,
+        }
+    }
+    /// The first watchpoint that `i` at `finger` triggered, given the
+    /// registers before it ran and the size of the array it abandoned.
+    fn watch(&self, finger: usize, i: Decoded, before: &Registers, abandoned: Option<usize>) -> Option<Hit> {
+        let value = |r: Register| -> Platter { r.into() };
+        let address = |r: Register| -> MemoryAddress { r.into() };
+        self.watchpoints.iter().find_map(|(&n, w)| {
+            let (old, new) = match w.target {
+                Target::Register(k) if before[k] != self.r[k] => (Some(value(before[k])), value(self.r[k])),
+                Target::Read { array, offset }
+                    if i.op == Op::Index && address(before[i.b]) == array && value(before[i.c]) == offset =>
+                {
+                    (None, value(self.r[i.a]))
+                }
+                Target::Write { array, offset }
+                    if i.op == Op::Amend && address(before[i.a]) == array && value(before[i.b]) == offset =>
+                {
+                    (None, value(before[i.c]))
+                }
+                Target::Alloc(array) if i.op == Op::Alloc && address(self.r[i.b]) == array => {
+                    (None, value(before[i.c]))
+                }
+                Target::Aband(array) if i.op == Op::Aband && address(before[i.c]) == array => {
+                    (None, abandoned? as Platter)
+                }
+                _ => return None,
+            };
+            w.accepts(new).then_some(Hit { watchpoint: n, finger, old, new })
+        })
+    }
+    /// Whether `run` and `run_limited` hand `sprint` many instructions at
+    /// once, rather than stepping.
+    fn sprints(&self) -> bool {
+        self.engine != Engine::Reference
+            && self.tracer.is_none()
+            && self.journal.is_none()
+            && self.watchpoints.is_empty()
+    }
+    /// Executes `n` instructions, stopping early at a halt or a fault.
+    fn sprint(&mut self, n: u64) -> Result<StepOutcome, Fault> {
+        let outcome = match self.engine {
+            #[cfg(feature = "jit")]
+            Engine::Jit => self.leap(n),
+            _ => self.race(n),
+        };
+        self.settle(outcome)
+    }
+    /// The JIT engine: runs the block at the finger, or with the fast
+    /// engine the instructions up to where a block may start if there is
+    /// none.
+    #[cfg(feature = "jit")]
+    fn leap(&mut self, n: u64) -> Result<StepOutcome, Fault> {
+        let mut done = 0;
+        while done < n {
+            let (ran, finger) = self.jit.run(self.ip, n - done, &mut self.code, &mut self.mem, &mut self.r);
+            self.ip = finger;
+            done += ran;
+            if ran == 0 {
+                let gap = self.jit.gap(self.ip).min(n - done);
+                if self.race(gap)? == StepOutcome::Halted {
+                    return Ok(StepOutcome::Halted);
+                }
+                done += gap;
+            }
+        }
+        Ok(StepOutcome::Running)
+    }
+    /// The fast engine. Each arm does what the matching arm of `execute`
+    /// does, on plain platters held in locals.
+    fn race(&mut self, n: u64) -> Result<StepOutcome, Fault> {
+        let registers: [Register; NUMBER_OF_REGISTERS] = self.r.clone().into();
+        let mut r: [Platter; NUMBER_OF_REGISTERS] = registers.map(Into::into);
+        let mut ip = self.ip;
+        let mut outcome = Ok(StepOutcome::Running);
+        for _ in 0..n {
+            let i = match self.code.as_slice().get(ip) {
+                Some(&Some(i)) => i,
+                _ => {
+                    self.ip = ip;
+                    outcome = Err(None);
+                    break;
+                }
+            };
+            let finger = ip;
+            ip += 1;
+            let number = |i: register::Index| Into::<usize>::into(i) & 7;
+            let (a, b, c) = (number(i.a), number(i.b), number(i.c));
+            let kind = match i.op {
+                Op::Move => {
+                    if r[c] != 0 {
+                        r[a] = r[b];
+                    }
+                    continue;
+                }
+                Op::Index => match self.mem.get(r[b].into(), r[c]) {
+                    Ok(value) => {
+                        r[a] = value;
+                        continue;
+                    }
+                    Err(kind) => kind,
+                },
+                Op::Amend => match self.mem.set(r[a].into(), r[b], r[c]) {
+                    Ok(()) => {
+                        if r[a] == 0 {
+                            self.code.update(r[b] as usize, r[c]);
+                            #[cfg(feature = "jit")]
+                            self.jit.invalidate(r[b] as usize);
+                        }
+                        continue;
+                    }
+                    Err(kind) => kind,
+                },
+                Op::Add => {
+                    r[a] = r[b].wrapping_add(r[c]);
+                    continue;
+                }
+                Op::Mult => {
+                    r[a] = r[b].wrapping_mul(r[c]);
+                    continue;
+                }
+                Op::Div => match r[c] {
+                    0 => FaultKind::DivideByZero,
+                    divisor => {
+                        r[a] = r[b] / divisor;
+                        continue;
+                    }
+                },
+                Op::NotAnd => {
+                    r[a] = !(r[b] & r[c]);
+                    continue;
+                }
+                Op::Halt => {
+                    self.ip = finger;
+                    outcome = Ok(StepOutcome::Halted);
+                    break;
+                }
+                Op::Alloc => match self.mem.alloc(r[c] as usize) {
+                    Ok(addr) => {
+                        r[b] = addr.into();
+                        continue;
+                    }
+                    Err(kind) => kind,
+                },
+                Op::Aband => match self.mem.free(r[c].into()) {
+                    Ok(()) => continue,
+                    Err(kind) => kind,
+                },
+                Op::Output => match r[c] {
+                    ch @ 256.. => FaultKind::OutputOutOfRange(ch),
+                    ch => match self.console.write_byte(ch as u8) {
+                        Ok(()) => continue,
+                        Err(e) => e.into(),
+                    },
+                },
+                Op::Input => match self.console.flush().and_then(|()| self.console.read_byte()) {
+                    Ok(byte) => {
+                        r[c] = byte.map_or(0xffff_ffff, Platter::from);
+                        continue;
+                    }
+                    Err(e) => e.into(),
+                },
+                Op::Load => match self.mem.load(r[b].into()) {
+                    Ok(()) => {
+                        if r[b] != 0 {
+                            self.decode();
+                        }
+                        ip = r[c] as usize;
+                        continue;
+                    }
+                    Err(kind) => kind,
+                },
+                Op::Orth => {
+                    r[a] = i.value;
+                    continue;
+                }
+            };
+            self.ip = finger;
+            outcome = Err(Some(kind));
+            break;
+        }
+        if outcome == Ok(StepOutcome::Running) {
+            self.ip = ip;
+        }
+        self.r = r.map(Into::into).into();
+        outcome.map_err(|kind| match kind {
+            Some(kind) => self.fault(kind),
+            None => self.peek().expect_err("decode cache out of sync with array 0"),
+        })
+    }
+    fn execute(&mut self, i: Decoded) -> Result<StepOutcome, FaultKind> {
         // println!("{:#010x}", raw_instruction);
         // println!("{i:?}");
         // println!("{}", i.as_pseudo_assembly());
@@ -67,7 +675,7 @@ impl Machine {
              *
              */
             Op::Move => {
-                if r[c] == 0.into() { return; }
+                if r[c] == 0.into() { return Ok(StepOutcome::Running); }
                 r[a] = r[b];
             }
             /*
@@ -76,7 +684,7 @@ impl Machine {
              *
              */
             Op::Index => {
-                r[a] = mem[r[b]][r[c]].into();
+                r[a] = mem.get(r[b].into(), r[c].into())?.into();
             }
             /*
                   The array identified by A is amended at the offset
@@ -84,7 +692,13 @@ impl Machine {
              *
              */
             Op::Amend => {
-                mem[r[a]][r[b]] = r[c].into();
+                let addr: MemoryAddress = r[a].into();
+                mem.set(addr, r[b].into(), r[c].into())?;
+                if addr == 0.into() {
+                    self.code.update(r[b].into(), r[c].into());
+                    #[cfg(feature = "jit")]
+                    self.jit.invalidate(r[b].into());
+                }
             }
             /*
                   The register A receives the value in register B plus 
@@ -109,6 +723,9 @@ impl Machine {
              *
              */
             Op::Div => {
+                if r[c] == 0.into() {
+                    return Err(FaultKind::DivideByZero);
+                }
                 r[a] = r[b] / r[c];
             }
             /*
@@ -126,7 +743,7 @@ impl Machine {
              *
              */
             Op::Halt => {
-                exit(0);
+                return Ok(StepOutcome::Halted);
             }
             /*
                   A new array is created with a capacity of platters
@@ -139,7 +756,7 @@ impl Machine {
              */
             Op::Alloc => {
                 let size: usize = r[c].into();
-                self.r[b] = mem.alloc(size).into();
+                self.r[b] = mem.alloc(size)?.into();
             }
             /*
                   The array identified by the register C is abandoned.
@@ -148,7 +765,7 @@ impl Machine {
              */
             Op::Aband => {
                 let addr: MemoryAddress = r[c].into();
-                mem.free(addr);
+                mem.free(addr)?;
             }
             /*
                   The value in the register C is displayed on the console
@@ -159,11 +776,11 @@ impl Machine {
             Op::Output => {
                 let ch: u32 = r[c].into();
                 if ch > 255 {
-                    panic!();
+                    return Err(FaultKind::OutputOutOfRange(ch));
+                }
+                if !self.journal.as_mut().is_some_and(Journal::rewrite) {
+                    self.console.write_byte(ch as u8)?;
                 }
-                let chars = [ch as u8];
-                let print_me = std::str::from_utf8(&chars).unwrap();
-                print!("{print_me}");
             }
             /*
                   The universal machine waits for input on the console.
@@ -175,11 +792,16 @@ impl Machine {
              *
              */
             Op::Input => {
-                let mut buf = [0u8; 1];
-                r[c] = match std::io::stdin().read(&mut buf) {
-                    Err(e) => panic!("Could not read from stdin: {}", e),
-                    Ok(0) => 0xffff_ffff,
-                    Ok(_) => buf[0] as u32,
+                let byte = match self.journal.as_mut().and_then(Journal::reread) {
+                    Some(byte) => byte,
+                    None => {
+                        self.console.flush()?;
+                        self.console.read_byte()?
+                    }
+                };
+                r[c] = match byte {
+                    None => 0xffff_ffff,
+                    Some(byte) => byte as u32,
                 }.into();
             }
             /*
@@ -197,8 +819,11 @@ impl Machine {
              *
              */
             Op::Load => {
-                let new_program: Program = mem[r[b]].clone().into();
-                self.load(new_program);
+                let addr: MemoryAddress = r[b].into();
+                mem.load(addr)?;
+                if addr != 0.into() {
+                    self.decode();
+                }
                 self.ip = self.r[c].into();
             }
             /*
@@ -207,21 +832,51 @@ impl Machine {
              *
              */
             Op::Orth => {
-               let tmp: Platter = i.value.into();
-               r[i.sa] = tmp.into();
+               r[a] = i.value.into();
             }
         }
+        Ok(StepOutcome::Running)
+    }
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::u32;
-
     use crate::memory::{ArrayOfPlatters, Collection, Memory};
     use crate::instruction::RawInstruction;
     use super::*;
 
+    fn looping() -> Machine<crate::console::Buffer> {
+        // orth r1, 0; load r0, r1 -- jumps back to the start forever
+        let source: Collection<Platter> = vec![0xd200_0000u32, 0xc000_0001].into();
+        let mut m = Machine::with_console(Default::default());
+        m.load(source.into());
+        m
+    }
+
+    #[test]
+    fn run_out_of_steps() {
+        let mut m = looping();
+        let limits = Limits { max_steps: Some(5), ..Default::default() };
+        assert_eq!(m.run_limited(limits), RunOutcome::BudgetExhausted);
+        assert_eq!(m.finger(), 1);
+        assert_eq!(m.run_limited(limits), RunOutcome::BudgetExhausted);
+        assert_eq!(m.finger(), 0);
+    }
+
+    #[test]
+    fn run_out_of_time() {
+        let mut m = looping();
+        let limits = Limits { deadline: Some(Instant::now()), ..Default::default() };
+        assert_eq!(m.run_limited(limits), RunOutcome::BudgetExhausted);
+        assert_eq!(m.finger(), 0);
+    }
+
     #[test]
     fn test_load() {
         let source: Collection<Platter> = vec![
@@ -229,7 +884,6 @@ mod tests {
             0xbabecafeu32,
         ].into();
         let mut expected = Memory::new();
-        expected.alloc(2);
         let zero: MemoryAddress = 0.into();
         expected[zero] = source.clone();
 
@@ -261,7 +915,7 @@ mod tests {
         let expected = 0xbabecafe;
         m.r[0.into()] = expected.into();
         m.r[1.into()] = 0xdeadbeef.into();
-        m.act();
+        m.act().unwrap();
         let got = m.r[0.into()].into();
 
         assert_eq!(expected, got);
@@ -288,7 +942,7 @@ mod tests {
         let expected = 0xdeadbeef;
         m.r[0.into()] = 0xbabecafe.into();
         m.r[1.into()] = expected.into();
-        m.act();
+        m.act().unwrap();
         let got = m.r[0.into()].into();
 
         assert_eq!(expected, got);
@@ -336,9 +990,9 @@ mod tests {
         m.r[3.into()] = 0.into();
         m.r[4.into()] = 42069.into();
         let expected = m.r[4.into()];
-        m.act();
-        m.act();
-        m.act();
+        m.act().unwrap();
+        m.act().unwrap();
+        m.act().unwrap();
         let got = m.r[0.into()];
 
         assert_eq!(expected, got);
@@ -377,9 +1031,9 @@ mod tests {
         let expected = 42069u32;
         m.r[4.into()] = expected.into();
         let idx = m.r[3.into()];
-        m.act();
+        m.act().unwrap();
         let addr = m.r[1.into()];
-        m.act();
+        m.act().unwrap();
         let got = m.mem[addr][idx];
 
         assert_eq!(expected, got);
@@ -405,7 +1059,7 @@ mod tests {
         m.load(p);
         m.r[0.into()] = 3_000_000_000.into();
         m.r[1.into()] = 2_000_000_000.into();
-        m.act();
+        m.act().unwrap();
         let expected = 705_032_704u32;
         let got = m.r[2.into()].into();
 
@@ -432,7 +1086,7 @@ mod tests {
         m.load(p);
         m.r[0.into()] = 900_000.into();
         m.r[1.into()] =   4_773.into();
-        m.act();
+        m.act().unwrap();
         let expected = 732_704u32;
         let got = m.r[2.into()].into();
 
@@ -459,7 +1113,7 @@ mod tests {
         m.load(p);
         m.r[0.into()] = 900000.into();
         m.r[1.into()] =   4773.into();
-        m.act();
+        m.act().unwrap();
         let expected =    188u32;
         let got = m.r[2.into()].into();
 
@@ -486,7 +1140,7 @@ mod tests {
         m.load(p);
         m.r[0.into()] = 0xbabe0000.into();
         m.r[1.into()] = 0x0000cafe.into();
-        m.act();
+        m.act().unwrap();
         let expected =    u32::MAX;
         let got = m.r[2.into()].into();
 
@@ -513,18 +1167,18 @@ mod tests {
         let mut m = Machine::new();
         m.load(p);
         m.r[0.into()] = 3.into();
-        m.act();
+        m.act().unwrap();
         let expected_b = 1u32;
         let r0 = m.r[1.into()];
-        let got_b = r0.clone().into();
+        let got_b = r0.into();
         assert_eq!(expected_b, got_b);
         let expected_m: ArrayOfPlatters = vec![0u32;3].into();
         let got_m = m.mem[r0].clone();
         assert_eq!(expected_m, got_m);
-        m.act();
+        m.act().unwrap();
         let expected_b = 2u32;
         let r0 = m.r[1.into()];
-        let got_b = r0.clone().into();
+        let got_b = r0.into();
         assert_eq!(expected_b, got_b);
         let expected_m: ArrayOfPlatters = vec![0u32;3].into();
         let got_m = m.mem[r0].clone();
@@ -561,12 +1215,12 @@ mod tests {
         let mut m = Machine::new();
         m.load(p);
         m.r[0.into()] = 0.into();
-        m.act();
+        m.act().unwrap();
         let first_addr = m.r[1.into()];
         m.r[0.into()] = first_addr;
-        m.act();
+        m.act().unwrap();
         m.r[0.into()] = 3.into();
-        m.act();
+        m.act().unwrap();
         let second_addr = m.r[1.into()];
         assert_eq!(first_addr, second_addr);
         let expected_m: ArrayOfPlatters = vec![0u32;3].into();
@@ -593,7 +1247,7 @@ mod tests {
         let p: Program = source.into();
         let mut m = Machine::new();
         m.load(p);
-        m.act();
+        m.act().unwrap();
         let got = m.r[2.into()].into();
 
         assert_eq!(expected, got);
@@ -650,10 +1304,10 @@ mod tests {
         m.r[0.into()] = 2.into();
         m.r[3.into()] = 1.into();
         m.r[4.into()] = program.into();
-        m.act();
-        m.act();
-        m.act();
-        let got = m.peek();
+        m.act().unwrap();
+        m.act().unwrap();
+        m.act().unwrap();
+        let got = m.peek().unwrap();
 
         assert_eq!(expected, got);
     }
@@ -1,29 +1,438 @@
-use machine::Machine;
-use program::Program;
-use std::io::Read;
-mod instruction;
-mod machine;
-mod macros;
-mod memory;
-mod op;
-mod program;
-mod register;
-mod types;
+use std::cell::RefCell;
+use std::io::{BufRead, BufWriter, Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+use um::{
+    asm, cfg::Cfg, compile, dap, debugger::Debugger, disasm, gdb, lockstep::{self, Lockstep}, profile::Profile, selftest, snapshot::Snapshot,
+    trace, Console, Engine, Limits, Machine, Program, RunOutcome, Stdio, StopAtEof,
+};
+
+const USAGE: &str = "\
+Usage: um [RUN OPTIONS] FILE
+       um [RUN OPTIONS] --resume SNAPSHOT
+       um disasm [--from OFFSET] [--to OFFSET] [--guess-data] [--source] FILE
+       um asm FILE [-o OUTPUT]
+       um compile FILE [-o OUTPUT]
+       um cfg [--format dot|json] FILE [-o OUTPUT]
+       um diff [--engines ENGINE,ENGINE] [--max-steps N] [--slice N] FILE
+       um selftest [--engine ENGINE]
+       um dap
+Run options:
+  --debug                   step through the program interactively
+  --gdb ADDRESS             wait for gdb on ADDRESS, HOST:PORT or a Unix socket
+                            path, and let it debug the program
+  --engine ENGINE           fast (the default), reference, or jit if built with it
+  --max-steps N             stop after N instructions
+  --save-on-exit SNAPSHOT   save the machine when it stops or input ends
+  --trace FILE              write every executed instruction to FILE as JSON lines
+  --trace-from OFFSET       only trace instructions at OFFSET and beyond
+  --trace-to OFFSET         only trace instructions before OFFSET
+  --trace-op NAME           only trace instructions with operator NAME (repeatable)
+  --profile                 count executions and allocations and print a report
+Diff runs FILE on two engines (reference,fast by default) and stops where they
+diverge, comparing them every --slice instructions. Cfg writes the control-flow
+graph of FILE as Graphviz DOT (the default) or JSON. Dap serves the Debug Adapter
+Protocol on standard input and output, for editors to debug programs with.";
+
+enum Command {
+    Run(RunOptions),
+    Disasm(disasm::Options),
+    Asm { output: Option<String> },
+    Compile { output: Option<String> },
+    Cfg { format: Format, output: Option<String> },
+    Diff(DiffOptions),
+    Selftest { engine: Engine },
+    Dap,
+}
+
+/// How `um cfg` writes the graph.
+#[derive(Clone, Copy)]
+enum Format {
+    Dot,
+    Json,
+}
+
+struct DiffOptions {
+    engines: [Engine; 2],
+    max_steps: Option<u64>,
+    slice: u64,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            engines: [Engine::Reference, Engine::Fast],
+            max_steps: None,
+            slice: lockstep::SLICE,
+        }
+    }
+}
+
+#[derive(Default)]
+struct RunOptions {
+    debug: bool,
+    gdb: Option<String>,
+    engine: Engine,
+    limits: Limits,
+    save_on_exit: Option<String>,
+    resume: Option<String>,
+    trace: Option<String>,
+    trace_filter: trace::Filter,
+    profile: bool,
+}
+
+struct Options {
+    filename: Option<String>,
+    command: Command,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut first = args.next();
+        let mut command = match first.as_deref() {
+            Some("disasm") => {
+                first = None;
+                Command::Disasm(disasm::Options::default())
+            }
+            Some("asm") => {
+                first = None;
+                Command::Asm { output: None }
+            }
+            Some("compile") => {
+                first = None;
+                Command::Compile { output: None }
+            }
+            Some("cfg") => {
+                first = None;
+                Command::Cfg { format: Format::Dot, output: None }
+            }
+            Some("diff") => {
+                first = None;
+                Command::Diff(DiffOptions::default())
+            }
+            Some("selftest") => {
+                first = None;
+                Command::Selftest { engine: Engine::default() }
+            }
+            Some("dap") => {
+                first = None;
+                Command::Dap
+            }
+            _ => Command::Run(RunOptions::default()),
+        };
+        let mut filename = None;
+        let mut args = first.into_iter().chain(args);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+            match (&mut command, arg.as_str()) {
+                (Command::Run(o), "--debug") => o.debug = true,
+                (Command::Run(o), "--gdb") => o.gdb = Some(value()?),
+                (Command::Run(o), "--engine") => o.engine = engine(&value()?)?,
+                (Command::Run(o), "--max-steps") => o.limits.max_steps = Some(number(&value()?)? as u64),
+                (Command::Run(o), "--save-on-exit") => o.save_on_exit = Some(value()?),
+                (Command::Run(o), "--resume") => o.resume = Some(value()?),
+                (Command::Run(o), "--trace") => o.trace = Some(value()?),
+                (Command::Run(o), "--trace-from") => o.trace_filter.fingers.start = number(&value()?)?,
+                (Command::Run(o), "--trace-to") => o.trace_filter.fingers.end = number(&value()?)?,
+                (Command::Run(o), "--trace-op") => o.trace_filter.ops.push(value()?.parse()?),
+                (Command::Run(o), "--profile") => o.profile = true,
+                (Command::Disasm(o), "--from") => o.range.start = number(&value()?)?,
+                (Command::Disasm(o), "--to") => o.range.end = number(&value()?)?,
+                (Command::Disasm(o), "--guess-data") => o.guess_data = true,
+                (Command::Disasm(o), "--source") => o.source = true,
+                (Command::Asm { output } | Command::Compile { output } | Command::Cfg { output, .. }, "-o") => {
+                    *output = Some(value()?)
+                }
+                (Command::Cfg { format, .. }, "--format") => *format = match value()?.as_str() {
+                    "dot" => Format::Dot,
+                    "json" => Format::Json,
+                    other => return Err(format!("Unknown format `{other}`")),
+                },
+                (Command::Diff(o), "--engines") => o.engines = engines(&value()?)?,
+                (Command::Diff(o), "--max-steps") => o.max_steps = Some(number(&value()?)? as u64),
+                (Command::Diff(o), "--slice") => o.slice = number(&value()?)? as u64,
+                (Command::Selftest { engine: e }, "--engine") => *e = engine(&value()?)?,
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {arg}")),
+                _ if filename.is_none() => filename = Some(arg),
+                _ => return Err("Please give 1 file!".to_string()),
+            }
+        }
+        if let Command::Run(RunOptions { debug: true, limits: Limits { max_steps: Some(_), .. }, .. }) = command {
+            return Err("--max-steps does not apply to --debug".to_string());
+        }
+        if let Command::Run(RunOptions { gdb: Some(_), debug, limits: Limits { max_steps, .. }, .. }) = &command {
+            if *debug || max_steps.is_some() {
+                return Err("--gdb cannot be combined with --debug or --max-steps".to_string());
+            }
+        }
+        if let Command::Run(RunOptions { trace: Some(_), profile: true, .. }) = command {
+            return Err("--profile cannot be combined with --trace".to_string());
+        }
+        match (&command, &filename) {
+            (Command::Run(RunOptions { resume: Some(_), .. }), Some(_)) => {
+                return Err("Please give either a file or --resume!".to_string())
+            }
+            (Command::Run(RunOptions { resume: Some(_), .. }), None) => {}
+            (Command::Selftest { .. }, Some(_)) => return Err("selftest takes no file".to_string()),
+            (Command::Selftest { .. }, None) => {}
+            (Command::Dap, Some(_)) => return Err("dap takes no file, the editor names it".to_string()),
+            (Command::Dap, None) => {}
+            (_, None) => return Err("Please give 1 file!".to_string()),
+            _ => {}
+        }
+        Ok(Self { filename, command })
+    }
+}
+
+/// Parses a decimal or `0x`-prefixed hexadecimal number.
+fn number(s: &str) -> Result<usize, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| format!("`{s}` is not a number"))
+}
+
+fn engine(s: &str) -> Result<Engine, String> {
+    match s {
+        "fast" => Ok(Engine::Fast),
+        "reference" => Ok(Engine::Reference),
+        #[cfg(feature = "jit")]
+        "jit" => Ok(Engine::Jit),
+        _ => Err(format!("Unknown engine `{s}`")),
+    }
+}
+
+/// Parses two engines separated by a comma.
+fn engines(s: &str) -> Result<[Engine; 2], String> {
+    match s.split_once(',') {
+        Some((a, b)) => Ok([engine(a)?, engine(b)?]),
+        None => Err(format!("`{s}` is not two engines separated by a comma")),
+    }
+}
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        println!("Please give 1 argument!");
-        return;
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            println!("{message}\n{USAGE}");
+            return;
+        }
+    };
+    let filename = options.filename.unwrap_or_default();
+    match options.command {
+        Command::Run(o) if o.save_on_exit.is_some() => {
+            run(Machine::with_console(StopAtEof(Stdio::new())), &filename, &o)
+        }
+        Command::Run(o) => run(Machine::new(), &filename, &o),
+        Command::Disasm(o) => {
+            let program = read_program(&filename);
+            disasm::disassemble(&program, &o, &mut std::io::stdout().lock())
+                .expect("Could not write disassembly.");
+        }
+        Command::Asm { output } => assemble(&filename, output),
+        Command::Compile { output } => compile(&filename, output),
+        Command::Cfg { format, output } => graph(&filename, format, output),
+        Command::Diff(o) => diff(&filename, &o),
+        Command::Dap => dap::serve(std::io::BufReader::new(std::io::stdin()), std::io::stdout().lock())
+            .expect("Could not talk to the editor."),
+        Command::Selftest { engine } => match selftest::sandmark(engine) {
+            Ok(elapsed) => println!("sandmark passed on {engine:?} in {:.1}s", elapsed.as_secs_f64()),
+            Err(failure) => {
+                eprintln!("sandmark failed on {engine:?}\n{failure}");
+                std::process::exit(1);
+            }
+        },
     }
-    let filename = &args[1];
+}
+
+fn read_program(filename: &str) -> Program {
     let mut source = Vec::<u8>::new();
     std::fs::File::open(filename)
         .expect("Could not open file.")
         .read_to_end(&mut source)
         .expect("Could not read file.");
-    let program: Program = source.into();
-    let mut machine = Machine::new();
-    machine.load(program);
-    machine.run();
+    source.into()
+}
+
+/// Runs the program in `filename`, or the snapshot to resume. When saving on
+/// exit, the end of input stops the machine rather than failing it.
+fn run<C: Console>(mut machine: Machine<C>, filename: &str, o: &RunOptions) {
+    match &o.resume {
+        Some(path) => {
+            let mut file = std::fs::File::open(path).expect("Could not open snapshot.");
+            machine.restore(Snapshot::read(&mut file).expect("Could not read snapshot."));
+        }
+        None => machine.load(read_program(filename)),
+    }
+    machine.set_engine(o.engine);
+    if let Some(path) = &o.trace {
+        let file = std::fs::File::create(path).expect("Could not create trace.");
+        let tracer = trace::JsonLines::new(BufWriter::new(file), o.trace_filter.clone());
+        machine.set_tracer(Box::new(tracer));
+    }
+    let profile = Rc::new(RefCell::new(Profile::new()));
+    if o.profile {
+        machine.set_tracer(Box::new(Rc::clone(&profile)));
+    }
+    let outcome = if o.debug {
+        let mut debugger = Debugger::new(machine);
+        debugger
+            .repl(Unlocked::default(), std::io::stdout())
+            .expect("Could not talk to the debugger console.");
+        machine = debugger.into_machine();
+        None
+    } else if let Some(address) = &o.gdb {
+        let ending;
+        (machine, ending) = serve_gdb(machine, address).expect("Could not talk to gdb.");
+        match ending {
+            gdb::Ending::Detached => Some(machine.run_limited(o.limits)),
+            gdb::Ending::Killed => None,
+        }
+    } else {
+        Some(machine.run_limited(o.limits))
+    };
+    if let Some(path) = &o.save_on_exit {
+        let mut file = std::fs::File::create(path).expect("Could not create snapshot.");
+        machine.snapshot().write(&mut file).expect("Could not write snapshot.");
+    }
+    if o.profile {
+        eprintln!("{}", profile.borrow());
+    }
+    match outcome {
+        Some(RunOutcome::Faulted(fault)) => {
+            eprintln!("The machine failed: {fault}");
+            std::process::exit(1);
+        }
+        Some(RunOutcome::BudgetExhausted) => {
+            eprintln!("The machine ran out of steps at finger {}", machine.finger());
+            std::process::exit(2);
+        }
+        _ => {}
+    }
+}
+
+/// Standard input for the debugger's commands, locked a byte at a time, so
+/// that the machine's console can read the rest while it runs.
+#[derive(Default)]
+struct Unlocked {
+    byte: Option<u8>,
+}
+
+impl Read for Unlocked {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.byte.take() {
+            Some(byte) if !buf.is_empty() => {
+                buf[0] = byte;
+                Ok(1)
+            }
+            byte => {
+                self.byte = byte;
+                std::io::stdin().read(buf)
+            }
+        }
+    }
+}
+
+impl BufRead for Unlocked {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.byte.is_none() {
+            let mut byte = [0];
+            if std::io::stdin().read(&mut byte)? == 1 {
+                self.byte = Some(byte[0]);
+            }
+        }
+        Ok(self.byte.as_slice())
+    }
+    fn consume(&mut self, amount: usize) {
+        if amount > 0 {
+            self.byte = None;
+        }
+    }
+}
+
+/// Lets one `gdb` connecting on `address` debug the machine.
+fn serve_gdb<C: Console>(machine: Machine<C>, address: &str) -> std::io::Result<(Machine<C>, gdb::Ending)> {
+    eprintln!("Waiting for gdb on {address}");
+    if address.contains(':') {
+        let (stream, _) = std::net::TcpListener::bind(address)?.accept()?;
+        stream.set_nodelay(true)?;
+        let mut stub = gdb::Stub::new(machine, stream);
+        let ending = stub.serve()?;
+        return Ok((stub.into_machine(), ending));
+    }
+    let listener = std::os::unix::net::UnixListener::bind(address)?;
+    let accepted = listener.accept();
+    std::fs::remove_file(address)?;
+    let mut stub = gdb::Stub::new(machine, accepted?.0);
+    let ending = stub.serve()?;
+    Ok((stub.into_machine(), ending))
+}
+
+/// Writes the program in `filename` as Rust source.
+fn compile(filename: &str, output: Option<String>) {
+    let program = read_program(filename);
+    let output = match output {
+        Some(output) => output.into(),
+        None => Path::new(filename).with_extension("rs"),
+    };
+    let file = std::fs::File::create(output).expect("Could not create output.");
+    let mut out = BufWriter::new(file);
+    compile::compile(&program, filename, &mut out)
+        .and_then(|()| out.flush())
+        .expect("Could not write output.");
+}
+
+/// Writes the control-flow graph of the program in `filename`, to standard
+/// output unless `output` is given.
+fn graph(filename: &str, format: Format, output: Option<String>) {
+    let cfg = Cfg::new(&read_program(filename));
+    let out: Box<dyn Write> = match output {
+        Some(output) => Box::new(std::fs::File::create(output).expect("Could not create output.")),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let mut out = BufWriter::new(out);
+    match format {
+        Format::Dot => cfg.write_dot(&mut out),
+        Format::Json => cfg.write_json(&mut out),
+    }
+    .and_then(|()| out.flush())
+    .expect("Could not write output.");
+}
+
+/// Runs the program in `filename` on two engines in lockstep.
+fn diff(filename: &str, o: &DiffOptions) {
+    let mut lockstep = Lockstep::new(read_program(filename), o.engines, Stdio::new());
+    lockstep.set_slice(o.slice);
+    match lockstep.run(o.max_steps) {
+        Ok(RunOutcome::Faulted(fault)) => {
+            eprintln!("The machine failed: {fault}");
+            std::process::exit(1);
+        }
+        Ok(RunOutcome::BudgetExhausted) => {
+            eprintln!("The machine ran out of steps at finger {}", lockstep.machines()[0].finger());
+            std::process::exit(2);
+        }
+        Ok(_) => {}
+        Err(divergence) => {
+            eprintln!("{divergence}");
+            std::process::exit(3);
+        }
+    }
+}
+
+fn assemble(filename: &str, output: Option<String>) {
+    let source = std::fs::read_to_string(filename).expect("Could not read file.");
+    let program = match asm::assemble(&source) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{filename}:{e}");
+            std::process::exit(1);
+        }
+    };
+    let output = match output {
+        Some(output) => output.into(),
+        None => Path::new(filename).with_extension("um"),
+    };
+    std::fs::write(output, program.to_bytes()).expect("Could not write program.");
 }
@@ -1,7 +1,5 @@
-use core::slice::Iter;
-use std::{collections::HashSet, ops::{Index, IndexMut}};
-use crate::{macros::{impl_from, impl_index, impl_into, impl_into_via}, register::Register};
-use std::hash::Hash;
+use std::{ops::{Index, IndexMut}, rc::Rc};
+use crate::{fault::FaultKind, macros::{impl_from, impl_index, impl_into, impl_into_via}, register::Register};
 
 
 pub type Platter = u32;
@@ -28,85 +26,238 @@ impl<T, A> FromIterator<A> for Collection<T> where Vec<T>: FromIterator<A> {
 
 type Array<T> = Collection<T>;
 pub type ArrayOfPlatters = Array<Platter>;
-/* TODO: Maybe have MemType be a HashMap<MemoryAddress, ArrayOfPlatters> ?*/
-type MemType = Collection<ArrayOfPlatters>;
-impl_index!(MemType, MemoryAddress, ArrayOfPlatters);
+/// An array identifier, holding its array while it is active. Arrays are
+/// shared between identifiers after a load and copied on their first write.
+type Slot = Option<Rc<ArrayOfPlatters>>;
+type MemType = Collection<Slot>;
+impl_index!(MemType, MemoryAddress, Slot);
 type MemoryAddresses = Vec<MemoryAddress>;
-#[derive(Debug, PartialEq, Clone)]
+/// The arrays of the machine. Identifiers of abandoned arrays are kept on
+/// a stack and handed out again before any new identifier is, so both
+/// allocating and abandoning take constant time.
+#[derive(Debug, Clone)]
 pub struct Memory {
     mem: MemType,
-    allocated: MemoryAddresses,
+    free: MemoryAddresses,
+    quota: Quota,
+    /// The platters in all active arrays, counting shared arrays once per
+    /// identifier.
+    platters: usize,
 }
 
-trait ToSet<T> {
-    fn as_set(&self) -> HashSet<T>;
-}
-
-impl<T> ToSet<T> for Iter<'_,T> where T: Eq + Hash + Clone {
-    fn as_set(&self) -> HashSet<T> {
-        self.clone().cloned().collect()
-    }
-}
-
-impl<T> ToSet<T> for [T] where T: Eq + Hash + Clone {
-    fn as_set(&self) -> HashSet<T> {
-        self.iter().cloned().collect()
-    }
+/// How much memory a program may allocate. `None` means no limit.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Quota {
+    /// The most platters in all active arrays together.
+    pub max_platters: Option<usize>,
+    /// The most platters in any one array.
+    pub max_array: Option<usize>,
+    /// The most active arrays, array 0 included.
+    pub max_arrays: Option<usize>,
 }
 
 
 impl Memory {
+    /// Memory holding only an empty '0' array.
     pub fn new() -> Self {
         Self {
-            mem: MemType::new(),
-            allocated: MemoryAddresses::new(),
+            mem: Collection(vec![Some(Rc::new(ArrayOfPlatters::new()))]),
+            free: MemoryAddresses::new(),
+            quota: Quota::default(),
+            platters: 0,
         }
     }
+    pub fn quota(&self) -> Quota {
+        self.quota
+    }
+    /// Limits future allocations. Arrays that are already active stay so,
+    /// even if they exceed the new quota.
+    pub fn set_quota(&mut self, quota: Quota) {
+        self.quota = quota;
+    }
+    /// The number of platters in all active arrays.
+    pub fn platters(&self) -> usize {
+        self.platters
+    }
+    /// The number of active arrays, array 0 included.
+    pub fn arrays(&self) -> usize {
+        self.len() - self.free.len()
+    }
+    /// The number of array identifiers handed out so far, active or not.
     pub fn len(&self) -> usize {
         self.mem.len()
     }
-    pub fn alloc(&mut self, size: usize) -> MemoryAddress {
-        let all = self.all_addresses();
-        let allocated = self.allocated.as_set();
-        let free = &all - &allocated;
-        let addr = match free.iter().last() {
+    pub fn is_empty(&self) -> bool {
+        self.mem.is_empty()
+    }
+    /// Whether `addr` identifies an allocated array that has not been abandoned.
+    pub fn is_active(&self, addr: MemoryAddress) -> bool {
+        let i: usize = addr.into();
+        matches!(self.mem.0.get(i), Some(Some(_)))
+    }
+    /// Allocates an array of `size` zero platters, failing rather than
+    /// going over the quota or running the host out of memory.
+    pub fn alloc(&mut self, size: usize) -> Result<MemoryAddress, FaultKind> {
+        let out_of_memory = FaultKind::OutOfMemory { size };
+        let platters = self.platters.checked_add(size).ok_or(out_of_memory.clone())?;
+        let over = |limit: Option<usize>, value: usize| limit.is_some_and(|max| value > max);
+        if over(self.quota.max_platters, platters)
+            || over(self.quota.max_array, size)
+            || over(self.quota.max_arrays, self.arrays() + 1)
+            || (self.free.is_empty() && fresh(self.len()).is_none())
+        {
+            return Err(out_of_memory);
+        }
+        let mut v = Vec::new();
+        v.try_reserve_exact(size).map_err(|_| out_of_memory)?;
+        v.resize(size, 0);
+        let v = Rc::new(Collection(v));
+        let addr = match self.free.pop() {
             None => {
                 let len = self.len();
-                if len > u32::MAX as usize {
-                    panic!("Trying to allocate more memory than the machine is allowed to have.");
-                }
-                let v: ArrayOfPlatters = vec![0u32; size].into();
-                self.mem.push(v);
+                self.mem.push(Some(v));
                 (len as u32).into()
             }
-            Some(&i) => {
-                self[i].resize(size, 0);
+            Some(i) => {
+                self.mem[i] = Some(v);
                 i
             }
         };
-        self.allocated.push(addr);
+        self.platters = platters;
         // println!("{} = alloc({})", addr, size);
-        addr
+        Ok(addr)
     }
-    pub fn free(&mut self, addr: MemoryAddress) {
+    pub fn free(&mut self, addr: MemoryAddress) -> Result<(), FaultKind> {
         // println!("free({})", addr);
         if addr == 0.into() {
-            panic!("Cannot abandon array 0");
+            return Err(FaultKind::AbandonProgram);
+        }
+        if !self.is_active(addr) {
+            return Err(FaultKind::InactiveArray(addr));
+        }
+        self.platters -= self[addr].len();
+        self.mem[addr] = None;
+        self.free.push(addr);
+        Ok(())
+    }
+    /// Duplicates the array identified by `addr` into the '0' array. The
+    /// duplicate shares its platters with the original until either is
+    /// amended, and loading the '0' array itself does nothing at all. The
+    /// duplicate counts against the quota of platters like any other array.
+    pub fn load(&mut self, addr: MemoryAddress) -> Result<(), FaultKind> {
+        let zero_addr: MemoryAddress = 0.into();
+        if addr == zero_addr {
+            return Ok(());
         }
-        match self.allocated.iter().enumerate().find(|(_, &a)| a == addr) {
-            None => panic!("Cannot abandon unallocated array {}", Into::<Platter>::into(addr)),
-            Some((i, _)) => {
-                self.allocated.remove(i);
+        if !self.is_active(addr) {
+            return Err(FaultKind::LoadInactive(addr));
+        }
+        let size = self[addr].len();
+        let platters = self.platters - self[zero_addr].len() + size;
+        if self.quota.max_platters.is_some_and(|max| platters > max) {
+            return Err(FaultKind::OutOfMemory { size });
+        }
+        self.mem[zero_addr] = self.mem[addr].clone();
+        self.platters = platters;
+        Ok(())
+    }
+    /// Every array identifier handed out so far, with its array if it is
+    /// active.
+    pub fn slots(&self) -> impl Iterator<Item = Option<&ArrayOfPlatters>> {
+        self.mem.0.iter().map(|slot| slot.as_deref())
+    }
+    /// The identifiers of abandoned arrays, in the reverse order in which
+    /// they will be reused.
+    pub fn free_list(&self) -> &[MemoryAddress] {
+        &self.free
+    }
+    /// Rebuilds memory from what `slots` and `free_list` returned.
+    pub fn from_parts(slots: Vec<Option<ArrayOfPlatters>>, free: Vec<MemoryAddress>) -> Result<Self, String> {
+        let platters = slots.iter().flatten().map(|array| array.len()).sum();
+        let mem = Collection(slots.into_iter().map(|slot| slot.map(Rc::new)).collect());
+        let memory = Self { mem, free, quota: Quota::default(), platters };
+        if !memory.is_active(0.into()) {
+            return Err("array 0 is not active".to_string());
+        }
+        let inactive: MemoryAddresses = (0..memory.len())
+            .filter(|&i| memory.mem.0[i].is_none())
+            .map(|i| (i as Platter).into())
+            .collect();
+        let mut free = memory.free.clone();
+        free.sort_by_key(|&addr| Into::<Platter>::into(addr));
+        if free != inactive {
+            return Err("the free list does not match the abandoned arrays".to_string());
+        }
+        Ok(memory)
+    }
+    /// The active array identified by `addr`, shared rather than copied.
+    pub(crate) fn shared(&self, addr: MemoryAddress) -> Option<Rc<ArrayOfPlatters>> {
+        self.mem.0.get(addr.0 as usize).cloned().flatten()
+    }
+    /// Undoes the `alloc` that returned `addr`, which took it off the free
+    /// list if `reused`.
+    pub(crate) fn unalloc(&mut self, addr: MemoryAddress, reused: bool) {
+        self.platters -= self[addr].len();
+        match reused {
+            true => {
+                self.mem[addr] = None;
+                self.free.push(addr);
+            }
+            false => {
+                self.mem.0.pop();
             }
         }
-        self[addr].resize(0, 0);
-        assert_eq!(self[addr].len(), 0);
     }
-
-
-    /* PRIVATE */
-    fn all_addresses(&self) -> HashSet<MemoryAddress> {
-        (0..self.len() as u32).map(|x|x.into()).collect::<MemoryAddresses>().as_set()
+    /// Undoes the `free` of `addr`, which held `array`.
+    pub(crate) fn unfree(&mut self, addr: MemoryAddress, array: Rc<ArrayOfPlatters>) {
+        self.free.pop();
+        self.platters += array.len();
+        self.mem[addr] = Some(array);
+    }
+    /// Undoes a `load` that replaced `array` as the '0' array.
+    pub(crate) fn unload(&mut self, array: Rc<ArrayOfPlatters>) {
+        let zero_addr: MemoryAddress = 0.into();
+        self.platters = self.platters - self[zero_addr].len() + array.len();
+        self.mem[zero_addr] = Some(array);
+    }
+    /// Replaces the '0' array with `program`, whatever the quota.
+    pub fn load_program(&mut self, program: ArrayOfPlatters) {
+        let zero_addr: MemoryAddress = 0.into();
+        self.platters = self.platters - self[zero_addr].len() + program.len();
+        self.mem[zero_addr] = Some(Rc::new(program));
+    }
+    /// The active array identified by `addr`.
+    pub fn array(&self, addr: MemoryAddress) -> Result<&ArrayOfPlatters, FaultKind> {
+        if !self.is_active(addr) {
+            return Err(FaultKind::InactiveArray(addr));
+        }
+        Ok(&self[addr])
+    }
+    /// The platter at `offset` in the array identified by `addr`.
+    #[inline]
+    pub fn get(&self, addr: MemoryAddress, offset: Platter) -> Result<Platter, FaultKind> {
+        match self.mem.0.get(addr.0 as usize) {
+            Some(Some(array)) => array
+                .0
+                .get(offset as usize)
+                .copied()
+                .ok_or(FaultKind::OutOfBounds { array: addr, offset }),
+            _ => Err(FaultKind::InactiveArray(addr)),
+        }
+    }
+    /// Stores `value` at `offset` in the array identified by `addr`.
+    #[inline]
+    pub fn set(&mut self, addr: MemoryAddress, offset: Platter, value: Platter) -> Result<(), FaultKind> {
+        match self.mem.0.get_mut(addr.0 as usize) {
+            Some(Some(array)) => match Rc::make_mut(array).0.get_mut(offset as usize) {
+                Some(p) => {
+                    *p = value;
+                    Ok(())
+                }
+                None => Err(FaultKind::OutOfBounds { array: addr, offset }),
+            },
+            _ => Err(FaultKind::InactiveArray(addr)),
+        }
     }
 }
 
@@ -122,21 +273,27 @@ impl<T> IndexMut<usize> for Collection<T> {
         &mut self.0[idx]
     }
 }
-impl<T> Collection<T> where T: Clone {
-    fn resize(&mut self, new_len: usize, value: T) {
-        let v = &mut self.0;
-        v.resize(new_len, value.into());
-    }
-}
 impl<T> Collection<T> {
-    fn len(&self) -> usize {
+    pub fn len(&self) -> usize {
         self.0.len()
     }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn as_slice(&self) -> &[T] {
+        &self.0
+    }
     fn push(&mut self, t: T) {
         self.0.push(t)
     }
 }
 
+impl std::fmt::Display for MemoryAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 impl Into<usize> for MemoryAddress {
     fn into(self) -> usize {
         self.0 as usize
@@ -153,14 +310,13 @@ impl From<Register> for MemoryAddress {
 impl Index<MemoryAddress> for Memory {
     type Output = ArrayOfPlatters;
     fn index(&self, idx: MemoryAddress) -> &Self::Output {
-        let i: Platter = idx.into();
-        &self.mem[i as usize]
+        self.mem[idx].as_ref().expect("Cannot index an inactive array")
     }
 }
 impl IndexMut<MemoryAddress> for Memory {
     fn index_mut(&mut self, idx: MemoryAddress) -> &mut Self::Output {
-        let i: Platter = idx.into();
-        &mut self.mem[i as usize]
+        let array = self.mem[idx].as_mut().expect("Cannot index an inactive array");
+        Rc::make_mut(array)
     }
 }
 impl Index<Register> for Memory {
@@ -196,3 +352,108 @@ impl<T> Collection<T> {
         Self(Vec::new())
     }
 }
+
+/// Memories are equal when they hold the same arrays under the same
+/// identifiers, whatever their quotas.
+impl PartialEq for Memory {
+    fn eq(&self, other: &Self) -> bool {
+        self.mem == other.mem && self.free == other.free
+    }
+}
+
+/// The identifier of a new array after `len` slots, or `None` once every
+/// identifier below 0xffffffff is taken.
+fn fresh(len: usize) -> Option<MemoryAddress> {
+    match len >= u32::MAX as usize {
+        true => None,
+        false => Some((len as Platter).into()),
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alloc_skips_zero() {
+        let mut m = Memory::new();
+        let a = m.alloc(1).unwrap();
+        let b = m.alloc(1).unwrap();
+        assert_eq!(a, 1.into());
+        assert_eq!(b, 2.into());
+    }
+
+    #[test]
+    fn free_reuses_identifier() {
+        let mut m = Memory::new();
+        let a = m.alloc(3).unwrap();
+        m.set(a, 2, 7).unwrap();
+        m.free(a).unwrap();
+        assert!(!m.is_active(a));
+        assert_eq!(m.get(a, 0), Err(FaultKind::InactiveArray(a)));
+        let b = m.alloc(2).unwrap();
+        assert_eq!(a, b);
+        assert_eq!(m[b].as_slice(), &[0, 0]);
+    }
+
+    #[test]
+    fn identifiers_run_out() {
+        assert_eq!(fresh(u32::MAX as usize - 1), Some(0xffff_fffe.into()));
+        assert_eq!(fresh(u32::MAX as usize), None);
+        assert_eq!(fresh(u32::MAX as usize + 1), None);
+        let slots = vec![Some(vec![0u32].into()), None, Some(vec![0u32].into())];
+        let mut m = Memory::from_parts(slots, vec![1.into()]).unwrap();
+        assert_eq!(m.alloc(1), Ok(1.into()));
+        assert_eq!(m.alloc(1), Ok(3.into()));
+    }
+
+    #[test]
+    fn free_inactive() {
+        let mut m = Memory::new();
+        assert_eq!(m.free(0.into()), Err(FaultKind::AbandonProgram));
+        assert_eq!(m.free(1.into()), Err(FaultKind::InactiveArray(1.into())));
+        let a = m.alloc(0).unwrap();
+        m.free(a).unwrap();
+        assert_eq!(m.free(a), Err(FaultKind::InactiveArray(a)));
+    }
+
+    #[test]
+    fn load_copies_on_write() {
+        let mut m = Memory::new();
+        let zero: MemoryAddress = 0.into();
+        let a = m.alloc(2).unwrap();
+        m.set(a, 0, 1).unwrap();
+        m.load(a).unwrap();
+        m.set(a, 1, 2).unwrap();
+        m.set(zero, 0, 3).unwrap();
+        assert_eq!(m[zero].as_slice(), &[3, 0]);
+        assert_eq!(m[a].as_slice(), &[1, 2]);
+        m.free(a).unwrap();
+        assert_eq!(m[zero].as_slice(), &[3, 0]);
+        assert_eq!(m.load(a), Err(FaultKind::LoadInactive(a)));
+    }
+
+    #[test]
+    fn quota() {
+        let mut m = Memory::new();
+        m.load_program(vec![0u32; 4].into());
+        m.set_quota(Quota { max_platters: Some(10), max_array: Some(5), max_arrays: Some(3) });
+        assert_eq!(m.alloc(6), Err(FaultKind::OutOfMemory { size: 6 }));
+        let a = m.alloc(5).unwrap();
+        assert_eq!(m.alloc(2), Err(FaultKind::OutOfMemory { size: 2 }));
+        let b = m.alloc(1).unwrap();
+        assert_eq!((m.platters(), m.arrays()), (10, 3));
+        assert_eq!(m.alloc(0), Err(FaultKind::OutOfMemory { size: 0 }));
+        assert_eq!(m.load(a), Err(FaultKind::OutOfMemory { size: 5 }));
+        m.load(b).unwrap();
+        m.free(a).unwrap();
+        assert_eq!((m.platters(), m.arrays()), (2, 2));
+        m.alloc(5).unwrap();
+    }
+}
@@ -1,4 +1,4 @@
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Op {
     Move,
     Index,
@@ -16,9 +16,14 @@ pub enum Op {
     Orth,
 }
 
-impl From<u8> for Op {
-    fn from(op: u8) -> Self {
-        match op {
+/// An operator number that names none of the 14 operators.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct InvalidOpcode(pub u8);
+
+impl TryFrom<u8> for Op {
+    type Error = InvalidOpcode;
+    fn try_from(op: u8) -> Result<Self, Self::Error> {
+        Ok(match op {
             0 => Self::Move,
             1 => Self::Index,
             2 => Self::Amend,
@@ -33,8 +38,8 @@ impl From<u8> for Op {
             11 => Self::Input,
             12 => Self::Load,
             13 => Self::Orth,
-            _ => panic!(),
-        }
+            _ => return Err(InvalidOpcode(op)),
+        })
     }
 }
 
@@ -58,3 +63,58 @@ impl Into<u32> for Op {
         }
     }
 }
+
+/// An operand in the assembly form of an instruction.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Operand {
+    /// Register A, or the special register A of `Op::Orth`.
+    A,
+    B,
+    C,
+    /// The immediate value of `Op::Orth`.
+    Value,
+}
+
+impl Op {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Self::Move => "cmov",
+            Self::Index => "index",
+            Self::Amend => "amend",
+            Self::Add => "add",
+            Self::Mult => "mul",
+            Self::Div => "div",
+            Self::NotAnd => "nand",
+            Self::Halt => "halt",
+            Self::Alloc => "alloc",
+            Self::Aband => "aband",
+            Self::Output => "out",
+            Self::Input => "in",
+            Self::Load => "load",
+            Self::Orth => "orth",
+        }
+    }
+    /// The operands of the assembly form, in order.
+    pub fn operands(&self) -> &'static [Operand] {
+        use Operand::*;
+        match self {
+            Self::Move | Self::Index | Self::Amend | Self::Add | Self::Mult | Self::Div
+            | Self::NotAnd => &[A, B, C],
+            Self::Halt => &[],
+            Self::Alloc | Self::Load => &[B, C],
+            Self::Aband | Self::Output | Self::Input => &[C],
+            Self::Orth => &[A, Value],
+        }
+    }
+}
+
+impl std::str::FromStr for Op {
+    type Err = String;
+    /// Parses the mnemonic or the name of an operator, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        (0..14)
+            .map(|op| Op::try_from(op).unwrap())
+            .find(|op| op.mnemonic().eq_ignore_ascii_case(s) || format!("{op:?}").eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown operator `{s}`"))
+    }
+}
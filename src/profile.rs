@@ -0,0 +1,211 @@
+//! Counts what a machine spends its instructions on.
+//!
+//! A `Profile` is a tracer. Executions are counted per operator and per
+//! finger, where fingers are keyed by program generation: generation 0 is
+//! the program the machine started with, and each Load from an array other
+//! than '0' starts the next generation.
+
+use std::{collections::HashMap, fmt, io};
+use crate::{
+    instruction::Instruction,
+    memory::{MemoryAddress, Platter},
+    op::Op,
+    trace::{Effect, Event, Tracer},
+};
+
+const NUMBER_OF_OPS: usize = 14;
+/// How many fingers the report lists.
+const HOT_SPOTS: usize = 20;
+
+#[derive(Debug, Default, Clone)]
+pub struct Profile {
+    ops: [u64; NUMBER_OF_OPS],
+    /// Per generation, per finger: the executions and the platter there.
+    fingers: Vec<Vec<(u64, Platter)>>,
+    allocs: Sizes,
+    abandons: Sizes,
+    /// The sizes of the arrays allocated while profiling that are active.
+    live: HashMap<MemoryAddress, Platter>,
+}
+
+/// How many arrays of which sizes.
+#[derive(Debug, Clone)]
+struct Sizes {
+    count: u64,
+    platters: u64,
+    largest: Platter,
+    /// Counts by size: 0, 1, 2-3, 4-7, ...
+    buckets: [u64; 33],
+}
+
+impl Default for Sizes {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            platters: 0,
+            largest: 0,
+            buckets: [0; 33],
+        }
+    }
+}
+
+impl Sizes {
+    fn add(&mut self, size: Platter) {
+        self.count += 1;
+        self.platters += size as u64;
+        self.largest = self.largest.max(size);
+        self.buckets[(Platter::BITS - size.leading_zeros()) as usize] += 1;
+    }
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// The number of instructions executed.
+    pub fn instructions(&self) -> u64 {
+        self.ops.iter().sum()
+    }
+    pub fn executions(&self, op: Op) -> u64 {
+        self.ops[Into::<u32>::into(op) as usize]
+    }
+    /// How often the instruction at `finger` in program `generation` was
+    /// executed.
+    pub fn executions_at(&self, generation: usize, finger: usize) -> u64 {
+        self.fingers
+            .get(generation)
+            .and_then(|g| g.get(finger))
+            .map_or(0, |&(count, _)| count)
+    }
+    /// The current program generation.
+    pub fn generation(&self) -> usize {
+        self.fingers.len().saturating_sub(1)
+    }
+}
+
+impl Tracer for Profile {
+    fn record(&mut self, e: &Event) -> io::Result<()> {
+        self.ops[Into::<u32>::into(e.instruction.op) as usize] += 1;
+        if self.fingers.is_empty() {
+            self.fingers.push(Vec::new());
+        }
+        let generation = self.fingers.last_mut().expect("there is a generation");
+        if generation.len() <= e.finger {
+            generation.resize(e.finger + 1, (0, 0));
+        }
+        generation[e.finger] = (generation[e.finger].0 + 1, e.platter);
+        match e.effect {
+            Some(Effect::Alloc { array, size }) => {
+                self.allocs.add(size);
+                self.live.insert(array, size);
+            }
+            Some(Effect::Aband { array }) => {
+                self.abandons.add(self.live.remove(&array).unwrap_or(0));
+            }
+            Some(Effect::Load { array, .. }) if array != 0.into() => self.fingers.push(Vec::new()),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Profile {
+    /// The hot-spot report.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.instructions();
+        let percent = |n: u64| n as f64 * 100.0 / total.max(1) as f64;
+        writeln!(f, "{total} instructions in {} program generations", self.fingers.len())?;
+        writeln!(f, "\nBy operator:")?;
+        let mut ops: Vec<(Op, u64)> = (0..NUMBER_OF_OPS as u8)
+            .map(|op| Op::try_from(op).expect("a valid operator"))
+            .map(|op| (op, self.executions(op)))
+            .filter(|&(_, n)| n > 0)
+            .collect();
+        ops.sort_by_key(|&(_, n)| std::cmp::Reverse(n));
+        for (op, n) in ops {
+            writeln!(f, "  {:<6} {n:>14} {:>6.2}%", op.mnemonic(), percent(n))?;
+        }
+        writeln!(f, "\nHot spots:")?;
+        let mut spots: Vec<(u64, usize, usize, Platter)> = self
+            .fingers
+            .iter()
+            .enumerate()
+            .flat_map(|(g, fingers)| {
+                fingers
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &(n, _))| n > 0)
+                    .map(move |(finger, &(n, platter))| (n, g, finger, platter))
+            })
+            .collect();
+        spots.sort_by_key(|&(n, g, finger, _)| (std::cmp::Reverse(n), g, finger));
+        writeln!(f, "  {:>5} {:>8} {:>14} {:>7}  instruction", "gen", "finger", "count", "")?;
+        for (n, g, finger, platter) in spots.into_iter().take(HOT_SPOTS) {
+            let instruction = Instruction::try_from(platter)
+                .map_or(String::new(), |i| i.to_string());
+            writeln!(f, "  {g:>5} {finger:>8} {n:>14} {:>6.2}%  {instruction}", percent(n))?;
+        }
+        writeln!(f, "\nAllocations: {}", self.allocs)?;
+        write!(f, "Abandons:    {}", self.abandons)?;
+        if !self.live.is_empty() {
+            write!(f, "\n{} arrays still active", self.live.len())?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Sizes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} arrays, {} platters, largest {}", self.count, self.platters, self.largest)?;
+        for (bucket, &n) in self.buckets.iter().enumerate().filter(|(_, &n)| n > 0) {
+            let range = match bucket {
+                0 => "0".to_string(),
+                1 => "1".to_string(),
+                _ => format!("{}-{}", 1u64 << (bucket - 1), (1u64 << bucket) - 1),
+            };
+            write!(f, "\n    size {range:<21} {n:>14}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::register::Registers;
+
+    fn event(finger: usize, platter: Platter, effect: Option<Effect>) -> Event {
+        Event {
+            finger,
+            platter,
+            instruction: Instruction::try_from(platter).unwrap(),
+            before: Registers::new(),
+            after: Registers::new(),
+            effect,
+        }
+    }
+
+    #[test]
+    fn counts() {
+        let mut p = Profile::new();
+        let alloc = |array: u32, size| Some(Effect::Alloc { array: array.into(), size });
+        p.record(&event(0, 0x8000_0000, alloc(1, 5))).unwrap();
+        p.record(&event(1, 0x8000_0000, alloc(2, 0))).unwrap();
+        p.record(&event(2, 0x9000_0000, Some(Effect::Aband { array: 1.into() }))).unwrap();
+        p.record(&event(3, 0xc000_0000, Some(Effect::Load { array: 0.into(), finger: 0 }))).unwrap();
+        p.record(&event(0, 0x8000_0000, alloc(1, 6))).unwrap();
+        p.record(&event(3, 0xc000_0000, Some(Effect::Load { array: 1.into(), finger: 0 }))).unwrap();
+        p.record(&event(0, 0x7000_0000, None)).unwrap();
+        assert_eq!(p.instructions(), 7);
+        assert_eq!(p.executions(Op::Alloc), 3);
+        assert_eq!(p.executions_at(0, 0), 2);
+        assert_eq!(p.executions_at(1, 0), 1);
+        assert_eq!(p.generation(), 1);
+        assert_eq!((p.allocs.count, p.allocs.platters, p.allocs.largest), (3, 11, 6));
+        assert_eq!(p.allocs.buckets[..4], [1, 0, 0, 2]);
+        assert_eq!((p.abandons.count, p.abandons.platters), (1, 5));
+        let report = p.to_string();
+        assert!(report.starts_with("7 instructions in 2 program generations\n"));
+        assert!(report.contains("      0        0              2  28.57%  alloc r0, r0\n"));
+    }
+}
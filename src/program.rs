@@ -36,6 +36,22 @@ impl From<Source> for Program {
     }
 }
 
+impl Program {
+    pub fn as_slice(&self) -> &[Platter] {
+        self.0.as_slice()
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    /// The program as it is stored in a `.umz` file.
+    pub fn to_bytes(&self) -> Source {
+        self.as_slice().iter().flat_map(|p| p.to_be_bytes()).collect()
+    }
+}
+
 impl PartialEq<ProgramType> for Program {
     fn eq(&self, other: &ProgramType) -> bool {
         self.0 == *other
@@ -67,5 +83,12 @@ mod tests {
         let expected: Program = tmp.into();
         assert_eq!(format!("{:x?}", got), format!("{:x?}", expected));
     }
+
+    #[test]
+    fn program_bytes() {
+        let source: Vec<u8> = vec![0xde, 0xad, 0xbe, 0xef, 0xba, 0xbe, 0xca, 0xfe];
+        let program: Program = source.clone().into();
+        assert_eq!(program.to_bytes(), source);
+    }
 }
 
@@ -7,6 +7,7 @@ use std::ops::{Add, BitAnd, Div, Mul, Not};
 
 type RegisterType = Platter;
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[repr(transparent)]
 pub struct Register(RegisterType);
 impl_from!(Register, RegisterType);
 impl_into!(Register, RegisterType);
@@ -18,16 +19,19 @@ impl Into<usize> for Register {
 
 
 pub type IndexType = u3;
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Index(IndexType);
 impl_from!(Index, IndexType);
 impl_into!(Index, IndexType);
 impl_into_extend!(Index, usize);
 
-const NUMBER_OF_REGISTERS: usize = 8;
+pub const NUMBER_OF_REGISTERS: usize = 8;
 type RegistersType = [Register; NUMBER_OF_REGISTERS];
+#[derive(Debug, PartialEq, Clone)]
+#[repr(transparent)]
 pub struct Registers(RegistersType);
 impl_from!(Registers, RegistersType);
+impl_into!(Registers, RegistersType);
 impl_index!(Registers, Index, Register);
 
 /* Register */
@@ -70,8 +74,26 @@ impl Registers {
         Registers([zero; NUMBER_OF_REGISTERS])
     }
 }
+impl Registers {
+    /// The registers as eight platters in a row, for compiled code.
+    #[cfg(feature = "jit")]
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut Platter {
+        self.0.as_mut_ptr().cast()
+    }
+}
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 /* Index */
+impl std::fmt::Display for Index {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let i: usize = (*self).into();
+        write!(f, "r{i}")
+    }
+}
 impl From<u32> for Index {
     fn from(value: u32) -> Self {
         let underlying: IndexType = value.into();
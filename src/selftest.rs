@@ -0,0 +1,121 @@
+//! The sandmark conformance suite, built into the crate so that `um
+//! selftest` can run it anywhere.
+//!
+//! Sandmark exercises every operator and prints a line per test it passes.
+//! A run conforms if it halts and prints exactly `sandmark-output.txt`.
+
+use std::{fmt, time::{Duration, Instant}};
+use crate::{console::Buffer, fault::Fault, machine::{Engine, Machine}, program::Program};
+
+pub const SANDMARK: &[u8] = include_bytes!("../sandmark.umz");
+/// What sandmark prints on a conforming machine.
+pub const EXPECTED: &[u8] = include_bytes!("../sandmark-output.txt");
+/// How many lines around a difference a `Mismatch` shows.
+const CONTEXT: usize = 2;
+
+/// Where some output first differs from what was expected.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Mismatch {
+    /// The offset of the first byte that differs.
+    pub offset: usize,
+    /// The line it is on, counting from 1.
+    pub line: usize,
+    /// The lines around it, as expected and as printed.
+    pub expected: Vec<String>,
+    pub actual: Vec<String>,
+}
+
+impl Mismatch {
+    /// Compares `actual` with `expected`, byte by byte.
+    pub fn find(expected: &[u8], actual: &[u8]) -> Option<Self> {
+        let offset = (0..expected.len().max(actual.len())).find(|&i| expected.get(i) != actual.get(i))?;
+        let line = expected[..offset.min(expected.len())].iter().filter(|&&b| b == b'\n').count();
+        let lines = |text: &[u8]| -> Vec<String> {
+            String::from_utf8_lossy(text)
+                .lines()
+                .skip(line.saturating_sub(CONTEXT))
+                .take(line.min(CONTEXT) + 1 + CONTEXT)
+                .map(String::from)
+                .collect()
+        };
+        Some(Self {
+            offset,
+            line: line + 1,
+            expected: lines(expected),
+            actual: lines(actual),
+        })
+    }
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let first = self.line.saturating_sub(CONTEXT).max(1);
+        write!(f, "output differs at byte {} on line {}", self.offset, self.line)?;
+        for (name, lines) in [("expected", &self.expected), ("actual", &self.actual)] {
+            write!(f, "\n{name}:")?;
+            for (n, text) in lines.iter().enumerate() {
+                let mark = if first + n == self.line { '>' } else { ' ' };
+                write!(f, "\n{mark} {:>4} | {text}", first + n)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How a run of sandmark fell short.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Failure {
+    pub fault: Option<Fault>,
+    pub mismatch: Option<Mismatch>,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut lines = Vec::new();
+        if let Some(fault) = &self.fault {
+            lines.push(format!("The machine failed: {fault}"));
+        }
+        if let Some(mismatch) = &self.mismatch {
+            lines.push(mismatch.to_string());
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+/// Runs sandmark on `engine` through an in-memory console, and returns how
+/// long it took.
+pub fn sandmark(engine: Engine) -> Result<Duration, Failure> {
+    let mut machine = Machine::with_console(Buffer::default());
+    machine.set_engine(engine);
+    machine.load(Program::from(SANDMARK.to_vec()));
+    let start = Instant::now();
+    let fault = machine.run().err();
+    let elapsed = start.elapsed();
+    let mismatch = Mismatch::find(EXPECTED, &machine.console().output);
+    match (fault, mismatch) {
+        (None, None) => Ok(elapsed),
+        (fault, mismatch) => Err(Failure { fault, mismatch }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mismatch() {
+        let expected = b"a\nb\nc\nd\ne\nf\n";
+        assert_eq!(Mismatch::find(expected, expected), None);
+        let m = Mismatch::find(expected, b"a\nb\nc\nd\nx\nf\n").unwrap();
+        assert_eq!((m.offset, m.line), (8, 5));
+        assert_eq!(m.expected, ["c", "d", "e", "f"]);
+        assert_eq!(m.actual, ["c", "d", "x", "f"]);
+        assert_eq!(
+            m.to_string(),
+            "output differs at byte 8 on line 5\nexpected:\n     3 | c\n     4 | d\n>    5 | e\n     6 | f\
+             \nactual:\n     3 | c\n     4 | d\n>    5 | x\n     6 | f"
+        );
+        let m = Mismatch::find(b"a\n", b"").unwrap();
+        assert_eq!((m.offset, m.line, m.expected, m.actual), (0, 1, vec!["a".to_string()], vec![]));
+    }
+}
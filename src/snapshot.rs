@@ -0,0 +1,126 @@
+//! The complete state of a machine, apart from its console, stored in a
+//! file so that a session can be resumed later.
+//!
+//! A snapshot file holds big-endian 32-bit words, like a `.umz` file:
+//!
+//! ```text
+//! "UMSS" version finger r0 .. r7
+//! slots { 0 | 1 length platters.. }..
+//! free-count free-identifiers..
+//! ```
+
+use std::io::{self, Read, Write};
+use crate::{
+    memory::{ArrayOfPlatters, Memory, MemoryAddress, Platter},
+    register::{Register, Registers, NUMBER_OF_REGISTERS},
+};
+
+const MAGIC: &[u8; 4] = b"UMSS";
+const VERSION: u32 = 1;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Snapshot {
+    pub finger: usize,
+    pub registers: Registers,
+    pub memory: Memory,
+}
+
+impl Snapshot {
+    pub fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        let finger = Platter::try_from(self.finger).map_err(|_| invalid("finger does not fit in a platter"))?;
+        let registers: [Register; NUMBER_OF_REGISTERS] = self.registers.clone().into();
+        let mut words = vec![VERSION, finger];
+        words.extend(registers.map(Into::<Platter>::into));
+        words.push(self.memory.len() as Platter);
+        for slot in self.memory.slots() {
+            match slot {
+                None => words.push(0),
+                Some(array) => {
+                    words.push(1);
+                    words.push(array.len() as Platter);
+                    words.extend_from_slice(array.as_slice());
+                }
+            }
+        }
+        let free = self.memory.free_list();
+        words.push(free.len() as Platter);
+        words.extend(free.iter().map(|&addr| Into::<Platter>::into(addr)));
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(words.iter().flat_map(|p| p.to_be_bytes()));
+        w.write_all(&bytes)?;
+        w.flush()
+    }
+    pub fn read(r: &mut impl Read) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a snapshot"));
+        }
+        let mut word = || -> io::Result<Platter> {
+            let mut buf = [0u8; 4];
+            r.read_exact(&mut buf)?;
+            Ok(Platter::from_be_bytes(buf))
+        };
+        let version = word()?;
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported snapshot version {version}")));
+        }
+        let finger = word()? as usize;
+        let mut registers = Registers::new();
+        for i in 0..NUMBER_OF_REGISTERS as u32 {
+            registers[i.into()] = word()?.into();
+        }
+        let mut slots = Vec::new();
+        for _ in 0..word()? {
+            slots.push(match word()? {
+                0 => None,
+                1 => {
+                    let len = word()?;
+                    let array: ArrayOfPlatters = (0..len).map(|_| word()).collect::<io::Result<Vec<Platter>>>()?.into();
+                    Some(array)
+                }
+                tag => return Err(invalid(&format!("bad slot tag {tag}"))),
+            });
+        }
+        let free = (0..word()?)
+            .map(|_| word().map(MemoryAddress::from))
+            .collect::<io::Result<Vec<MemoryAddress>>>()?;
+        let memory = Memory::from_parts(slots, free).map_err(|e| invalid(&e))?;
+        Ok(Self { finger, registers, memory })
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_read() {
+        let mut memory = Memory::new();
+        let a = memory.alloc(3).unwrap();
+        let b = memory.alloc(1).unwrap();
+        memory.alloc(2).unwrap();
+        memory.set(a, 1, 0xdead_beef).unwrap();
+        memory.free(b).unwrap();
+        let mut registers = Registers::new();
+        registers[7.into()] = 42.into();
+        let snapshot = Snapshot { finger: 17, registers, memory };
+        let mut bytes = Vec::new();
+        snapshot.write(&mut bytes).unwrap();
+        assert_eq!(&bytes[..8], b"UMSS\0\0\0\x01");
+        let read = Snapshot::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read, snapshot);
+    }
+
+    #[test]
+    fn read_invalid() {
+        let kind = |bytes: &[u8]| Snapshot::read(&mut &bytes[..]).unwrap_err().kind();
+        assert_eq!(kind(b"UMZZ"), io::ErrorKind::InvalidData);
+        assert_eq!(kind(b"UMSS\0\0\0\x02"), io::ErrorKind::InvalidData);
+        assert_eq!(kind(b"UMSS\0\0\0\x01\0\0"), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,218 @@
+//! Records every instruction a machine executes, so that two runs can be
+//! compared instruction by instruction.
+//!
+//! `JsonLines` writes one JSON object per executed instruction:
+//!
+//! ```text
+//! {"finger":2,"platter":805306451,"instruction":"add r1, r2, r3",
+//!  "before":[0,0,5,7,0,0,0,0],"after":[0,12,5,7,0,0,0,0],"effect":null}
+//! ```
+//!
+//! (on a single line). The effect is `null` or an object naming what the
+//! instruction did outside the registers, e.g.
+//! `{"amend":{"array":1,"offset":0,"value":42}}`.
+
+use std::{cell::RefCell, io::{self, Write}, ops::Range, rc::Rc};
+use crate::{
+    instruction::Instruction,
+    memory::{MemoryAddress, Platter},
+    op::Op,
+    register::{Register, Registers, NUMBER_OF_REGISTERS},
+};
+
+/// What an instruction did outside the registers.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Effect {
+    Amend { array: MemoryAddress, offset: Platter, value: Platter },
+    Alloc { array: MemoryAddress, size: Platter },
+    Aband { array: MemoryAddress },
+    Output(u8),
+    /// The byte read, or `None` at the end of input.
+    Input(Option<u8>),
+    Load { array: MemoryAddress, finger: Platter },
+}
+
+/// One executed instruction.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Event {
+    pub finger: usize,
+    pub platter: Platter,
+    pub instruction: Instruction,
+    pub before: Registers,
+    pub after: Registers,
+    pub effect: Option<Effect>,
+}
+
+/// Receives an event for every instruction a machine completes. Faulting
+/// instructions are not recorded.
+pub trait Tracer {
+    /// Whether to record the instruction at `finger` with operator `op`.
+    /// Events the tracer does not want are not even built.
+    fn wants(&self, _finger: usize, _op: Op) -> bool {
+        true
+    }
+    fn record(&mut self, event: &Event) -> io::Result<()>;
+    /// Called when the machine stops running.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Lets the owner of a tracer look at it while the machine holds it.
+impl<T: Tracer> Tracer for Rc<RefCell<T>> {
+    fn wants(&self, finger: usize, op: Op) -> bool {
+        self.borrow().wants(finger, op)
+    }
+    fn record(&mut self, event: &Event) -> io::Result<()> {
+        self.borrow_mut().record(event)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.borrow_mut().flush()
+    }
+}
+
+/// Which instructions to trace.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Filter {
+    /// Offsets into array 0.
+    pub fingers: Range<usize>,
+    /// Operators, or every operator if empty.
+    pub ops: Vec<Op>,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            fingers: 0..usize::MAX,
+            ops: Vec::new(),
+        }
+    }
+}
+
+impl Filter {
+    pub fn matches(&self, finger: usize, op: Op) -> bool {
+        self.fingers.contains(&finger) && (self.ops.is_empty() || self.ops.contains(&op))
+    }
+}
+
+impl Effect {
+    /// The effect of `instruction`, worked out from the registers before
+    /// and after it was executed.
+    pub fn of(instruction: &Instruction, before: &Registers, after: &Registers) -> Option<Self> {
+        let value = |r: &Registers, i| -> Platter { r[i].into() };
+        let (a, b, c) = (instruction.a, instruction.b, instruction.c);
+        Some(match instruction.op {
+            Op::Amend => Self::Amend {
+                array: value(before, a).into(),
+                offset: value(before, b),
+                value: value(before, c),
+            },
+            Op::Alloc => Self::Alloc {
+                array: value(after, b).into(),
+                size: value(before, c),
+            },
+            Op::Aband => Self::Aband { array: value(before, c).into() },
+            Op::Output => Self::Output(value(before, c) as u8),
+            Op::Input => Self::Input(u8::try_from(value(after, c)).ok()),
+            Op::Load => Self::Load {
+                array: value(before, b).into(),
+                finger: value(before, c),
+            },
+            _ => return None,
+        })
+    }
+}
+
+/// Writes events as JSON, one per line.
+pub struct JsonLines<W> {
+    out: W,
+    pub filter: Filter,
+}
+
+impl<W: Write> JsonLines<W> {
+    pub fn new(out: W, filter: Filter) -> Self {
+        Self { out, filter }
+    }
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write> Tracer for JsonLines<W> {
+    fn wants(&self, finger: usize, op: Op) -> bool {
+        self.filter.matches(finger, op)
+    }
+    fn record(&mut self, e: &Event) -> io::Result<()> {
+        let effect = match e.effect {
+            None => "null".to_string(),
+            Some(Effect::Amend { array, offset, value }) => {
+                format!(r#"{{"amend":{{"array":{array},"offset":{offset},"value":{value}}}}}"#)
+            }
+            Some(Effect::Alloc { array, size }) => {
+                format!(r#"{{"alloc":{{"array":{array},"size":{size}}}}}"#)
+            }
+            Some(Effect::Aband { array }) => format!(r#"{{"aband":{{"array":{array}}}}}"#),
+            Some(Effect::Output(byte)) => format!(r#"{{"output":{byte}}}"#),
+            Some(Effect::Input(None)) => r#"{"input":null}"#.to_string(),
+            Some(Effect::Input(Some(byte))) => format!(r#"{{"input":{byte}}}"#),
+            Some(Effect::Load { array, finger }) => {
+                format!(r#"{{"load":{{"array":{array},"finger":{finger}}}}}"#)
+            }
+        };
+        writeln!(
+            self.out,
+            r#"{{"finger":{},"platter":{},"instruction":"{}","before":{},"after":{},"effect":{effect}}}"#,
+            e.finger,
+            e.platter,
+            e.instruction,
+            registers(&e.before),
+            registers(&e.after),
+        )
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+fn registers(r: &Registers) -> String {
+    let values: [Register; NUMBER_OF_REGISTERS] = r.clone().into();
+    let values: Vec<String> = values.iter().map(|&v| Into::<Platter>::into(v).to_string()).collect();
+    format!("[{}]", values.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_line() {
+        let instruction: Instruction = 0x1000_000au32.try_into().unwrap(); // index r0, r1, r2
+        let mut before = Registers::new();
+        before[1.into()] = 3.into();
+        let mut after = before.clone();
+        after[0.into()] = 9.into();
+        let event = Event {
+            finger: 4,
+            platter: 0x1000_000a,
+            effect: Effect::of(&instruction, &before, &after),
+            instruction,
+            before,
+            after,
+        };
+        let mut tracer = JsonLines::new(Vec::new(), Filter::default());
+        tracer.record(&event).unwrap();
+        assert_eq!(
+            String::from_utf8(tracer.into_inner()).unwrap(),
+            r#"{"finger":4,"platter":268435466,"instruction":"index r0, r1, r2","before":[0,3,0,0,0,0,0,0],"after":[9,3,0,0,0,0,0,0],"effect":null}"#.to_string() + "\n"
+        );
+    }
+
+    #[test]
+    fn filter() {
+        let filter = Filter { fingers: 2..4, ops: vec![Op::Add, Op::Halt] };
+        assert!(filter.matches(2, Op::Add));
+        assert!(!filter.matches(4, Op::Add));
+        assert!(!filter.matches(3, Op::Load));
+        assert!(Filter::default().matches(usize::MAX - 1, Op::Load));
+    }
+}
@@ -4,7 +4,7 @@ use crate::macros::{
 use std::ops::BitAndAssign;
 
 #[expect(non_camel_case_types)]
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct u3(u8);
 impl_into!(u3, u8);
 impl_into_extend!(u3, usize);
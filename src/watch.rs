@@ -0,0 +1,148 @@
+//! Watchpoints stop a machine right after an instruction touches what they
+//! watch, see `Machine::add_watchpoint`.
+
+use std::{fmt, str::FromStr};
+use crate::{
+    memory::{MemoryAddress, Platter},
+    register::Index,
+};
+
+/// What a watchpoint watches, and what its value is when it triggers.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Target {
+    /// A register changing, to its new value.
+    Register(Index),
+    /// An Index of the platter at `offset` in `array`, and the value read.
+    Read { array: MemoryAddress, offset: Platter },
+    /// An Amend of the platter at `offset` in `array`, and the value written.
+    Write { array: MemoryAddress, offset: Platter },
+    /// An Alloc returning this identifier, and the size of the array.
+    Alloc(MemoryAddress),
+    /// The Aband of this identifier, and the size of the array.
+    Aband(MemoryAddress),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Compares the value of a triggered watchpoint with a constant.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Condition {
+    pub comparison: Comparison,
+    pub value: Platter,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Watchpoint {
+    pub target: Target,
+    /// Only stop if the new value meets this.
+    pub condition: Option<Condition>,
+}
+
+/// A watchpoint that triggered.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Hit {
+    /// The number of the watchpoint.
+    pub watchpoint: usize,
+    /// The offset into array 0 of the instruction that triggered it.
+    pub finger: usize,
+    /// What a watched register held before.
+    pub old: Option<Platter>,
+    pub new: Platter,
+}
+
+impl Condition {
+    pub fn holds(&self, new: Platter) -> bool {
+        match self.comparison {
+            Comparison::Eq => new == self.value,
+            Comparison::Ne => new != self.value,
+            Comparison::Lt => new < self.value,
+            Comparison::Le => new <= self.value,
+            Comparison::Gt => new > self.value,
+            Comparison::Ge => new >= self.value,
+        }
+    }
+}
+
+impl Watchpoint {
+    pub fn new(target: Target) -> Self {
+        Self { target, condition: None }
+    }
+    /// Whether `new` is a value to stop at.
+    pub fn accepts(&self, new: Platter) -> bool {
+        self.condition.is_none_or(|c| c.holds(new))
+    }
+}
+
+impl FromStr for Comparison {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "==" => Ok(Self::Eq),
+            "!=" => Ok(Self::Ne),
+            "<" => Ok(Self::Lt),
+            "<=" => Ok(Self::Le),
+            ">" => Ok(Self::Gt),
+            ">=" => Ok(Self::Ge),
+            _ => Err(format!("unknown comparison `{s}`")),
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Register(register) => write!(f, "{register}"),
+            Self::Read { array, offset } => write!(f, "read {array} {offset}"),
+            Self::Write { array, offset } => write!(f, "write {array} {offset}"),
+            Self::Alloc(array) => write!(f, "alloc {array}"),
+            Self::Aband(array) => write!(f, "aband {array}"),
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.target)?;
+        match self.condition {
+            Some(c) => write!(f, " if {} {:#x}", c.comparison, c.value),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conditions() {
+        let mut w = Watchpoint::new(Target::Write { array: 1.into(), offset: 4 });
+        assert!(w.accepts(7));
+        w.condition = Some(Condition { comparison: ">=".parse().unwrap(), value: 5 });
+        assert!(w.accepts(5) && !w.accepts(4));
+        assert_eq!(w.to_string(), "write 1 4 if >= 0x5");
+        assert!("=".parse::<Comparison>().is_err());
+    }
+}
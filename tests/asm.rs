@@ -0,0 +1,40 @@
+use um::{asm::assemble, disasm, Buffer, Machine, Program};
+
+const HELLO: &str = r#"
+; Prints a zero-terminated string and halts.
+        orth r1, message
+        orth r2, 1
+        orth r4, print
+        orth r5, done
+loop:   index r3, r0, r1        ; r0 is 0, so this reads array 0
+        cmov r5, r4, r3         ; print unless the platter is 0
+        load r0, r5
+print:  out r3
+        add r1, r1, r2
+        orth r5, done
+        orth r6, loop
+        load r0, r6
+done:   halt
+message:
+        .data "Hello, world!\n", 0
+"#;
+
+#[test]
+fn hello_world() {
+    let program = assemble(HELLO).unwrap();
+    let mut m = Machine::with_console(Buffer::default());
+    m.load(program);
+    m.run().unwrap();
+    assert_eq!(m.console().output, b"Hello, world!\n");
+}
+
+#[test]
+fn sandmark_round_trip() {
+    let source = std::fs::read("sandmark.umz").unwrap();
+    let program: Program = source.clone().into();
+    let options = disasm::Options { source: true, ..Default::default() };
+    let mut text = Vec::new();
+    disasm::disassemble(&program, &options, &mut text).unwrap();
+    let assembled = assemble(&String::from_utf8(text).unwrap()).unwrap();
+    assert_eq!(assembled.to_bytes(), source);
+}
@@ -0,0 +1,43 @@
+#![allow(dead_code)]
+
+use um::{ArrayOfPlatters, Console, Instruction, Machine, Op, Platter, Program, RawInstruction};
+
+pub fn op(op: Op, a: u32, b: u32, c: u32) -> Platter {
+    let raw: RawInstruction = Instruction {
+        op,
+        a: a.into(),
+        b: b.into(),
+        c: c.into(),
+        sa: 0.into(),
+        value: 0.into(),
+    }.into();
+    raw.into()
+}
+
+pub fn orth(sa: u32, value: u32) -> Platter {
+    let raw: RawInstruction = Instruction {
+        op: Op::Orth,
+        a: 0.into(),
+        b: 0.into(),
+        c: 0.into(),
+        sa: sa.into(),
+        value: value.into(),
+    }.into();
+    raw.into()
+}
+
+pub fn machine(code: Vec<Platter>) -> Machine {
+    machine_with(code, um::Stdio::new())
+}
+
+pub fn machine_with<C: Console>(code: Vec<Platter>, console: C) -> Machine<C> {
+    let source: ArrayOfPlatters = code.into();
+    let program: Program = source.into();
+    let mut m = Machine::with_console(console);
+    m.load(program);
+    m
+}
+
+pub fn reg<C: Console>(m: &Machine<C>, i: u32) -> Platter {
+    m.registers()[i.into()].into()
+}
@@ -0,0 +1,84 @@
+//! Builds the output of `um compile` and runs it against the reference
+//! engine.
+
+use std::{io::Write, process::{Command, Stdio}};
+use um::{asm::assemble, compile::compile, Buffer, Engine, Machine};
+
+/// Prints a string, echoes its input through a fresh array each byte, then
+/// amends the block it is in to print one more byte before halting.
+const PROGRAM: &str = r#"
+        orth r1, message
+        orth r2, 1
+        orth r4, print
+        orth r5, echo
+loop:   index r3, r0, r1
+        cmov r5, r4, r3
+        load r0, r5
+print:  out r3
+        add r1, r1, r2
+        orth r5, echo
+        orth r6, loop
+        load r0, r6
+echo:   in r3
+        add r7, r3, r2          ; 0 at the end of input
+        orth r5, done
+        orth r4, more
+        cmov r5, r4, r7
+        load r0, r5
+more:   alloc r6, r2
+        amend r6, r0, r3
+        index r3, r6, r0
+        aband r6
+        out r3
+        orth r5, echo
+        load r0, r5
+done:   orth r1, '!'
+        orth r4, template
+        index r3, r0, r4
+        orth r4, patched
+        amend r0, r4, r3
+patched:
+        halt
+        halt
+template:
+        out r1
+message:
+        .data "Hello, ", 0
+"#;
+
+const INPUT: &[u8] = b"world";
+
+#[test]
+fn matches_reference() {
+    let program = assemble(PROGRAM).unwrap();
+    let mut source = Vec::new();
+    compile(&program, "test.umz", &mut source).unwrap();
+
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("compiled");
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    let manifest = format!(
+        "[package]\nname = \"compiled\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+         [dependencies]\num = {{ path = {:?} }}\n\n[workspace]\n",
+        env!("CARGO_MANIFEST_DIR"),
+    );
+    std::fs::write(dir.join("Cargo.toml"), manifest).unwrap();
+    std::fs::write(dir.join("src/main.rs"), source).unwrap();
+    let mut child = Command::new(env!("CARGO"))
+        .args(["run", "--quiet", "--manifest-path"])
+        .arg(dir.join("Cargo.toml"))
+        .env_remove("CARGO_TARGET_DIR")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(INPUT).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{output:?}");
+
+    let mut m = Machine::with_console(Buffer::new(INPUT.to_vec()));
+    m.set_engine(Engine::Reference);
+    m.load(program);
+    m.run().unwrap();
+    assert_eq!(m.console().output, b"Hello, world!");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&m.console().output));
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use common::{machine_with, op, orth, reg};
+use um::{snapshot::Snapshot, Buffer, Machine, Op, RunOutcome, Scripted, StopAtEof};
+
+/// Echoes input to output until the end of input, then halts.
+fn echo() -> Vec<u32> {
+    vec![
+        orth(2, 1),
+        orth(5, 7),
+        orth(3, 10),
+        op(Op::Input, 0, 0, 1),
+        op(Op::Add, 4, 1, 2),
+        op(Op::Move, 3, 5, 4),
+        op(Op::Load, 0, 0, 3),
+        op(Op::Output, 0, 0, 1),
+        orth(3, 2),
+        op(Op::Load, 0, 0, 3),
+        op(Op::Halt, 0, 0, 0),
+    ]
+}
+
+#[test]
+fn echo_buffer() {
+    let mut m = machine_with(echo(), Buffer::new(b"Hello, world!".to_vec()));
+    m.run().unwrap();
+    assert_eq!(m.console().output, b"Hello, world!");
+    assert!(m.console().input.is_empty());
+}
+
+#[test]
+fn output_is_binary() {
+    let bytes: Vec<u8> = (0..=255).collect();
+    let mut m = machine_with(echo(), Buffer::new(bytes.clone()));
+    m.run().unwrap();
+    assert_eq!(m.into_console().output, bytes);
+}
+
+#[test]
+fn end_of_input() {
+    let mut m = machine_with(vec![op(Op::Input, 0, 0, 1)], Buffer::default());
+    m.step().unwrap();
+    assert_eq!(reg(&m, 1), 0xffff_ffff);
+}
+
+#[test]
+fn echo_script() {
+    let console = Scripted::new(b"abc".to_vec(), Buffer::new(b"def".to_vec()));
+    let mut m = machine_with(echo(), console);
+    m.run().unwrap();
+    assert_eq!(m.console().inner().output, b"abcdef");
+}
+
+#[test]
+fn stop_at_eof_and_resume() {
+    let mut m = machine_with(echo(), StopAtEof(Buffer::new(b"ab".to_vec())));
+    assert_eq!(m.run_limited(Default::default()), RunOutcome::WaitingForInput);
+    assert_eq!(m.finger(), 3);
+    let mut file = Vec::new();
+    m.snapshot().write(&mut file).unwrap();
+
+    let mut resumed = Machine::with_console(Buffer::new(b"cd".to_vec()));
+    resumed.restore(Snapshot::read(&mut file.as_slice()).unwrap());
+    assert_eq!(reg(&resumed, 1), b'b' as u32);
+    resumed.run().unwrap();
+    assert_eq!(resumed.console().output, b"cd");
+}
+
+/// Records writes and flushes so tests can check when output becomes visible.
+#[derive(Default)]
+struct Recorder {
+    input: Vec<u8>,
+    events: Vec<String>,
+}
+
+impl um::Console for Recorder {
+    fn read_byte(&mut self) -> std::io::Result<Option<u8>> {
+        self.events.push("read".into());
+        Ok(self.input.pop())
+    }
+    fn write_byte(&mut self, byte: u8) -> std::io::Result<()> {
+        self.events.push(format!("write {byte}"));
+        Ok(())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.events.push("flush".into());
+        Ok(())
+    }
+}
+
+#[test]
+fn flush_before_input_and_halt() {
+    let console = Recorder { input: vec![b'b', b'a'], ..Default::default() };
+    let mut m = machine_with(echo(), console);
+    m.run().unwrap();
+    assert_eq!(m.console().events, [
+        "flush", "read", "write 97",
+        "flush", "read", "write 98",
+        "flush", "read",
+        "flush",
+    ]);
+}
+
+#[test]
+fn flush_on_fault() {
+    let code = vec![orth(1, 33), op(Op::Output, 0, 0, 1), op(Op::Div, 0, 1, 2)];
+    let mut m = machine_with(code, Recorder::default());
+    assert!(m.run().is_err());
+    assert_eq!(m.console().events, ["write 33", "flush"]);
+}
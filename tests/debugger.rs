@@ -0,0 +1,36 @@
+//! Runs `um --debug` on a program that reads input, with the debugger's
+//! commands and the program's input sharing standard input.
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+use um::asm::assemble;
+
+#[test]
+fn reads_input() {
+    let program = assemble("in r1\n out r1\n halt").unwrap();
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("reads-input.um");
+    std::fs::write(&path, program.to_bytes()).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_um"))
+        .arg("--debug")
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"continue\nx").unwrap();
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while child.try_wait().unwrap().is_none() {
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            panic!("um --debug hangs on input");
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{output:?}");
+    assert!(stdout.contains("x") && stdout.contains("The machine halted\n"), "{stdout}");
+}
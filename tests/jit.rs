@@ -0,0 +1,28 @@
+//! Runs the JIT engine on sandmark, at first in lockstep with the fast
+//! engine.
+#![cfg(feature = "jit")]
+
+use um::{lockstep::Lockstep, selftest, Buffer, Engine, Program, RunOutcome};
+
+/// Runs sandmark on both engines for up to `steps` instructions, comparing
+/// them every `slice`.
+fn lockstep(steps: u64, slice: u64) -> RunOutcome {
+    let source = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/sandmark.umz")).unwrap();
+    let mut lockstep = Lockstep::new(Program::from(source), [Engine::Fast, Engine::Jit], Buffer::default());
+    lockstep.set_slice(slice);
+    lockstep.run(Some(steps)).unwrap_or_else(|divergence| panic!("{divergence}"))
+}
+
+#[test]
+fn sandmark_start() {
+    assert_eq!(lockstep(2_000_000, 10_007), RunOutcome::BudgetExhausted);
+}
+
+/// All of sandmark, against its expected output: in lockstep it would take
+/// twice as long.
+#[test]
+fn sandmark() {
+    if let Err(failure) = selftest::sandmark(Engine::Jit) {
+        panic!("{failure}");
+    }
+}
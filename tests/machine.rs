@@ -0,0 +1,309 @@
+mod common;
+
+use common::{machine, machine_with, op, orth, reg};
+use um::{
+    journal::Journal,
+    watch::{Comparison, Condition, Hit, Target, Watchpoint},
+    trace::{Effect, Event, Tracer},
+    Engine, Fault, FaultKind, Instruction, Limits, Machine, MemoryAddress, Op, Platter, Program,
+    RunOutcome, StepOutcome,
+};
+
+#[test]
+fn load_from_bytes() {
+    let source: Vec<u8> = vec![0xd0, 0x00, 0x00, 0x2a];
+    let program: Program = source.into();
+    let mut m = Machine::new();
+    m.load(program);
+    assert_eq!(m.finger(), 0);
+    assert_eq!(m.peek().unwrap(), Instruction {
+        op: Op::Orth,
+        a: 0.into(),
+        b: 5.into(),
+        c: 2.into(),
+        sa: 0.into(),
+        value: 42.into(),
+    });
+}
+
+#[test]
+fn step_advances_finger() {
+    let mut m = machine(vec![
+        orth(1, 40),
+        orth(2, 2),
+        op(Op::Add, 0, 1, 2),
+    ]);
+    m.step().unwrap();
+    assert_eq!(m.finger(), 1);
+    m.step().unwrap();
+    m.step().unwrap();
+    assert_eq!(m.finger(), 3);
+    assert_eq!(reg(&m, 0), 42);
+}
+
+#[test]
+fn registers_can_be_set() {
+    let mut m = machine(vec![
+        op(Op::Mult, 0, 1, 2),
+    ]);
+    m.registers_mut()[1.into()] = 6.into();
+    m.registers_mut()[2.into()] = 7.into();
+    m.step().unwrap();
+    assert_eq!(reg(&m, 0), 42);
+}
+
+#[test]
+fn arrays_are_inspectable() {
+    let mut m = machine(vec![
+        orth(2, 4),
+        op(Op::Alloc, 0, 1, 2),
+        orth(3, 3),
+        orth(4, 0xbeef),
+        op(Op::Amend, 1, 3, 4),
+    ]);
+    for _ in 0..5 {
+        m.step().unwrap();
+    }
+    let addr: MemoryAddress = reg(&m, 1).into();
+    assert_ne!(addr, 0.into());
+    assert!(m.memory().is_active(addr));
+    assert_eq!(m.memory()[addr].as_slice(), &[0, 0, 0, 0xbeef]);
+    assert_eq!(m.memory()[MemoryAddress::from(0)].len(), 5);
+}
+
+#[test]
+fn abandoned_arrays_are_inactive() {
+    let mut m = machine(vec![
+        orth(2, 1),
+        op(Op::Alloc, 0, 1, 2),
+        op(Op::Aband, 0, 0, 1),
+    ]);
+    m.step().unwrap();
+    m.step().unwrap();
+    let addr: MemoryAddress = reg(&m, 1).into();
+    assert!(m.memory().is_active(addr));
+    m.step().unwrap();
+    assert!(!m.memory().is_active(addr));
+}
+
+/// Steps `code` until it faults, and checks that both engines fail the same
+/// way when running it.
+fn fault(code: Vec<Platter>) -> Fault {
+    let mut m = machine(code.clone());
+    let fault = loop {
+        match m.step() {
+            Ok(StepOutcome::Running) => {}
+            Ok(StepOutcome::Halted) => panic!("machine halted"),
+            Ok(StepOutcome::Watchpoint(_)) => panic!("machine stopped at a watchpoint"),
+            Err(fault) => break fault,
+        }
+    };
+    for engine in [Engine::Reference, Engine::Fast] {
+        let mut other = machine(code.clone());
+        other.set_engine(engine);
+        assert_eq!(other.run(), Err(fault.clone()), "{engine:?}");
+        assert_eq!(other.finger(), m.finger());
+        assert_eq!(other.registers(), m.registers());
+    }
+    fault
+}
+
+#[test]
+fn halt_keeps_finger() {
+    let mut m = machine(vec![
+        orth(0, 1),
+        op(Op::Halt, 0, 0, 0),
+    ]);
+    assert_eq!(m.step(), Ok(StepOutcome::Running));
+    assert_eq!(m.step(), Ok(StepOutcome::Halted));
+    assert_eq!(m.finger(), 1);
+    assert_eq!(m.step(), Ok(StepOutcome::Halted));
+    assert_eq!(m.run(), Ok(()));
+}
+
+#[test]
+fn fault_invalid_opcode() {
+    let f = fault(vec![orth(0, 1), 0xe000_0000]);
+    assert_eq!(f.kind, FaultKind::InvalidOpcode(14));
+    assert_eq!(f.finger, 1);
+    assert_eq!(f.instruction, None);
+}
+
+#[test]
+fn fault_finger_out_of_range() {
+    let f = fault(vec![orth(0, 1)]);
+    assert_eq!(f.kind, FaultKind::FingerOutOfRange);
+    assert_eq!(f.finger, 1);
+}
+
+#[test]
+fn fault_divide_by_zero() {
+    let f = fault(vec![orth(1, 7), op(Op::Div, 0, 1, 2)]);
+    assert_eq!(f.kind, FaultKind::DivideByZero);
+    assert_eq!(f.finger, 1);
+    assert_eq!(f.instruction.unwrap().op, Op::Div);
+}
+
+#[test]
+fn fault_inactive_array() {
+    let f = fault(vec![orth(1, 5), op(Op::Index, 0, 1, 2)]);
+    assert_eq!(f.kind, FaultKind::InactiveArray(5.into()));
+}
+
+#[test]
+fn fault_out_of_bounds() {
+    let f = fault(vec![
+        orth(2, 2),
+        op(Op::Alloc, 0, 1, 2),
+        op(Op::Amend, 1, 2, 2),
+    ]);
+    assert_eq!(f.kind, FaultKind::OutOfBounds { array: 1.into(), offset: 2 });
+    assert_eq!(f.finger, 2);
+}
+
+#[test]
+fn fault_abandon() {
+    let f = fault(vec![op(Op::Aband, 0, 0, 1)]);
+    assert_eq!(f.kind, FaultKind::AbandonProgram);
+    let f = fault(vec![orth(1, 3), op(Op::Aband, 0, 0, 1)]);
+    assert_eq!(f.kind, FaultKind::InactiveArray(3.into()));
+}
+
+#[test]
+fn fault_output_out_of_range() {
+    let f = fault(vec![orth(1, 256), op(Op::Output, 0, 0, 1)]);
+    assert_eq!(f.kind, FaultKind::OutputOutOfRange(256));
+}
+
+#[test]
+fn fault_load_inactive() {
+    let f = fault(vec![
+        orth(2, 1),
+        op(Op::Alloc, 0, 1, 2),
+        op(Op::Aband, 0, 0, 1),
+        op(Op::Load, 0, 1, 0),
+    ]);
+    assert_eq!(f.kind, FaultKind::LoadInactive(1.into()));
+    assert_eq!(f.finger, 3);
+    assert_eq!(f.to_string(), format!("cannot load program from inactive array 1 at finger 3 ({:?})", f.instruction.clone().unwrap()));
+}
+
+#[test]
+fn fast_engine_sees_amended_program() {
+    // Overwrites the division by zero at 5 with a halt before reaching it.
+    let code = vec![
+        orth(2, 5),
+        orth(3, 0x7000_0000 >> 7),
+        op(Op::Mult, 3, 3, 4),
+        op(Op::Amend, 0, 2, 3),
+        orth(5, 1),
+        op(Op::Div, 0, 0, 0),
+    ];
+    for engine in [Engine::Reference, Engine::Fast] {
+        let mut m = machine(code.clone());
+        m.set_engine(engine);
+        m.registers_mut()[4.into()] = 128.into();
+        let limits = Limits { max_steps: Some(3), ..Default::default() };
+        assert_eq!(m.run_limited(limits), RunOutcome::BudgetExhausted);
+        assert_eq!(m.finger(), 3);
+        assert_eq!(m.run_limited(Limits::default()), RunOutcome::Halted);
+        assert_eq!(m.finger(), 5);
+        assert_eq!(reg(&m, 5), 1);
+    }
+}
+
+/// Collects events where the test can still see them after handing the
+/// tracer to the machine.
+struct Collect(std::rc::Rc<std::cell::RefCell<Vec<Event>>>, Op);
+
+impl Tracer for Collect {
+    fn wants(&self, _finger: usize, op: Op) -> bool {
+        op == self.1
+    }
+    fn record(&mut self, event: &Event) -> std::io::Result<()> {
+        self.0.borrow_mut().push(event.clone());
+        Ok(())
+    }
+}
+
+#[test]
+fn trace_load() {
+    let events = std::rc::Rc::default();
+    let mut m = machine(vec![
+        orth(2, 1),
+        op(Op::Alloc, 0, 1, 2),
+        orth(3, 0x7000_0000 >> 7),
+        op(Op::Mult, 3, 3, 4),
+        op(Op::Amend, 1, 0, 3),
+        op(Op::Load, 0, 1, 0),
+    ]);
+    m.registers_mut()[4.into()] = 128.into();
+    m.set_tracer(Box::new(Collect(std::rc::Rc::clone(&events), Op::Load)));
+    m.run().unwrap();
+    let events = events.borrow();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].finger, 5);
+    assert_eq!(events[0].platter, op(Op::Load, 0, 1, 0));
+    assert_eq!(events[0].effect, Some(Effect::Load { array: 1.into(), finger: 0 }));
+}
+
+#[test]
+fn unstep_everything() {
+    let mut m = machine_with(vec![
+        op(Op::Input, 0, 0, 1),
+        op(Op::Output, 0, 0, 1),
+        orth(2, 2),
+        op(Op::Alloc, 0, 3, 2),
+        op(Op::Amend, 3, 0, 1),
+        op(Op::Aband, 0, 0, 3),
+        op(Op::Alloc, 0, 4, 2),
+        orth(6, 0x7000_0000 >> 7),
+        orth(7, 128),
+        op(Op::Mult, 6, 6, 7),
+        op(Op::Amend, 4, 0, 6),
+        op(Op::Load, 0, 4, 0),
+    ], um::Scripted::new(b"x".to_vec(), um::Buffer::default()));
+    let start = m.snapshot();
+    m.set_journal(Journal::new());
+    m.run().unwrap();
+    let end = m.snapshot();
+    let journal = m.journal().unwrap();
+    assert_eq!(journal.steps(), 12);
+    assert_eq!(journal.last_write(1.into(), 0).map(|e| e.step), Some(10));
+    assert_eq!(journal.last_write(0.into(), 5).map(|e| (e.step, e.op)), Some((11, Op::Load)));
+    while m.unstep().unwrap() {}
+    assert_eq!(m.snapshot(), start);
+    assert_eq!(m.unstep(), Ok(false));
+    // Running forwards again reads the same input, and does not repeat output.
+    m.run().unwrap();
+    assert_eq!(m.snapshot(), end);
+    assert_eq!(m.console().inner().output, b"x");
+    // Changes the journal did not see clear it, rather than undo wrongly.
+    assert!(!m.journal().unwrap().is_empty());
+    m.amend(1.into(), 0, 7).unwrap();
+    assert!(m.journal().unwrap().is_empty());
+    assert_eq!(m.unstep(), Ok(false));
+}
+
+#[test]
+fn watchpoints() {
+    let mut m = machine(vec![
+        orth(2, 3),
+        op(Op::Alloc, 0, 1, 2),
+        op(Op::Index, 3, 1, 0),
+        op(Op::Index, 3, 1, 0),
+        op(Op::Aband, 0, 0, 1),
+        op(Op::Halt, 0, 0, 0),
+    ]);
+    let alloc = m.add_watchpoint(Watchpoint::new(Target::Alloc(1.into())));
+    let read = m.add_watchpoint(Watchpoint::new(Target::Read { array: 1.into(), offset: 0 }));
+    let mut aband = Watchpoint::new(Target::Aband(1.into()));
+    aband.condition = Some(Condition { comparison: Comparison::Lt, value: 3 });
+    m.add_watchpoint(aband);
+    let hit = |watchpoint, finger, new| RunOutcome::Watchpoint(Hit { watchpoint, finger, old: None, new });
+    assert_eq!(m.run_limited(Limits::default()), hit(alloc, 1, 3));
+    assert_eq!(m.finger(), 2);
+    assert_eq!(m.run_limited(Limits::default()), hit(read, 2, 0));
+    m.remove_watchpoint(read);
+    assert_eq!(m.run_limited(Limits::default()), RunOutcome::Halted);
+}
@@ -0,0 +1,10 @@
+//! Runs the sandmark conformance suite, see `um::selftest`.
+
+use um::{selftest, Engine};
+
+#[test]
+fn sandmark() {
+    if let Err(failure) = selftest::sandmark(Engine::Fast) {
+        panic!("{failure}");
+    }
+}